@@ -0,0 +1,68 @@
+#![no_main]
+use gofish::entities::{
+    card::Rank,
+    config::{GameConfig, Variant},
+    game::{Action, Game},
+};
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+/// Rules of the game, kept within what `/newgame` accepts.
+#[derive(Arbitrary, Debug)]
+struct Rules {
+    sudden_death: bool,
+    hand_size: Option<u8>,
+    ranks: u8,
+    pairs: bool,
+    fish_again: bool,
+    seed: u64,
+}
+
+/// Actions on a handful of players, so most of them reach the engine.
+#[derive(Arbitrary, Debug)]
+enum FuzzAction {
+    Start,
+    Join(u8),
+    Ask(u8, usize, u8),
+    Draw(u8, u8),
+    Leave(u8),
+}
+
+fn id(player: u8) -> String {
+    (player % 6).to_string()
+}
+
+fn rank(value: u8) -> Rank {
+    Rank::new(value % 13 + 1).unwrap()
+}
+
+fuzz_target!(|input: (Rules, Vec<FuzzAction>)| {
+    let (rules, actions) = input;
+    let config = GameConfig {
+        variant: if rules.sudden_death {
+            Variant::SuddenDeath
+        } else {
+            Variant::Standard
+        },
+        hand_size: rules.hand_size.map(|size| size % 10 + 1),
+        ranks: rules.ranks % 13 + 1,
+        group_size: if rules.pairs { 2 } else { 4 },
+        fish_again: rules.fish_again,
+        seed: Some(rules.seed),
+        ..GameConfig::default()
+    };
+    let mut game = Game::new(config);
+    for action in actions {
+        let action = match action {
+            FuzzAction::Start => Action::Start,
+            FuzzAction::Join(player) => Action::Join(id(player), id(player), None, None),
+            FuzzAction::Ask(player, to, value) => Action::Ask(id(player), to, rank(value)),
+            FuzzAction::Draw(player, value) => Action::Draw(id(player), rank(value)),
+            FuzzAction::Leave(player) => Action::Leave(id(player)),
+        };
+        let _ = game.execute(action);
+    }
+    // Whatever happened, the game can still be replayed
+    let export = game.export();
+    export.replay(export.moves.len()).unwrap();
+});
@@ -0,0 +1,14 @@
+#![no_main]
+use gofish::command::Command;
+use gofish::entities::{card::Rank, config::GameConfig};
+use gofish::keyboards::CallbackData;
+use libfuzzer_sys::fuzz_target;
+use teloxide::utils::command::BotCommand;
+
+// Everything typed by the players or sent back by the buttons goes through one of these
+fuzz_target!(|text: &str| {
+    let _ = Command::parse(text, "gofish");
+    let _ = text.parse::<Rank>();
+    let _ = text.parse::<GameConfig>();
+    let _ = text.parse::<CallbackData>();
+});
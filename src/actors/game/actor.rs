@@ -1,17 +1,32 @@
-use super::messages::{GameActorMsg, GameCommand, IsOver};
+use super::messages::{Callback, GameActorMsg};
+use super::registry::GameOverNotifier;
 use crate::actors::AsyncActor;
-use crate::alias::Cx;
-use crate::entities::game::{Action, GameResults, GameState, TurnEvent};
-use crate::entities::player::Player;
-use crate::errors::ActionError;
+use crate::keyboards::CallbackData;
+use crate::metrics;
+use crate::session::{
+    game::GameSession,
+    messages::{GameCommand, Identity, Response},
+    telegram::TelegramTransport,
+    Transport,
+};
 use crate::templates::*;
-use crate::{actors::game::messages::Message, entities::game::Game};
-use anyhow::Result;
+use crate::{actors::game::messages::Message, entities::game::GameState};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use teloxide::prelude::*;
+use tokio::time::Instant;
 
+// Times in a row a game can be restored after a crash before giving up on it
+const MAX_RESTARTS: u32 = 3;
+
+/// Plays a game session in a Telegram chat.
 pub struct GameActor {
-    game: Game,
+    bot: AutoSend<Bot>,
+    session: GameSession,
+    transport: TelegramTransport,
+    restarts: u32,
+    game_over: GameOverNotifier,
+    stopped: bool,
 }
 
 #[async_trait]
@@ -19,162 +34,159 @@ impl AsyncActor<GameActorMsg> for GameActor {
     type Output = ();
 
     async fn handle(&mut self, msg: GameActorMsg) -> Result<Self::Output> {
-        match msg {
+        let handled = match msg {
             GameActorMsg::Message(msg) => self.handle_message(msg).await,
-            GameActorMsg::IsOver(msg) => self.handle_is_over(msg).await,
-        }
-    }
-}
-
-impl GameActor {
-    async fn handle_is_over(&self, IsOver(responder): IsOver) -> Result<()> {
-        let _ = responder.send(self.is_over());
-        Ok(())
-    }
-
-    async fn handle_message(&mut self, Message(cx, command): Message) -> Result<()> {
-        let result = match command {
-            GameCommand::Ask(to, card) => self.ask(&cx, to, card).await,
-            GameCommand::Join => self.join(&cx).await,
-            GameCommand::Start => self.start(&cx).await,
-            GameCommand::Status => self.status(&cx).await,
+            GameActorMsg::Callback(msg) => self.handle_callback(*msg).await,
+            GameActorMsg::Stop => {
+                self.stop();
+                return Ok(());
+            }
         };
-
-        if let Err(root_err) = result {
-            let message = match root_err.downcast_ref::<ActionError>() {
-                Some(err) => match err {
-                    ActionError::InvalidQuestion(_, _) => INVALID_QUESTION,
-                    ActionError::InvalidPlayerId(_) => INVALID_PLAYER,
-                    ActionError::CannotAsk(_) => NOT_YOUR_TURN,
-                    ActionError::CannotDraw(_) => ERROR_DRAWING,
-                    ActionError::GameAlreadyStarted => GAME_ALREADY_STARTED,
-                    ActionError::PlayerAlreadyJoined(_) => ALREADY_JOINED,
-                },
-                None => UNKNOWN_ERROR,
-            };
-            cx.answer(message).await?;
-        }
-        Ok(())
-    }
-}
-
-impl GameActor {
-    pub fn new() -> Self {
-        Self { game: Game::new() }
+        // Only the crashes in a row count, a message handled without one starts over
+        self.restarts = 0;
+        self.check_game_over();
+        handled
     }
 
-    pub fn is_over(&self) -> bool {
-        match self.game.state {
-            GameState::GameOver(_) => true,
-            _ => false,
-        }
+    fn is_stopped(&self) -> bool {
+        self.stopped
     }
 
-    async fn start(&mut self, cx: &Cx) -> Result<()> {
-        self.game.execute(Action::Start)?;
-        cx.answer_dice().await?;
-        cx.answer(GAME_STARTED).await?;
-        self.send_status_to_players(&cx, &self.game.players).await?;
-        self.check_game_state(&cx).await
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
     }
 
-    async fn join(&mut self, cx: &Cx) -> Result<()> {
-        let user = cx.update.from().unwrap();
-        self.game.execute(Action::Join(
-            user.id.clone().to_string(),
-            user.first_name.clone(),
-        ))?;
-        cx.answer(welcome(&user.first_name)).await?;
-        Ok(())
+    async fn on_deadline(&mut self) -> Result<()> {
+        let response = self.session.on_deadline();
+        let delivered = self.deliver(response).await;
+        self.restarts = 0;
+        self.check_game_over();
+        delivered
     }
 
-    async fn ask(&mut self, cx: &Cx, to: usize, card: usize) -> Result<()> {
-        let from = cx.update.from().unwrap();
-        let player_id = format!("{}", from.id);
-        let events = self
-            .game
-            .execute(Action::Ask(player_id.clone(), to, card as u8))?;
-        for event in events {
-            let msg = match event {
-                TurnEvent::Took(quantity) if quantity == 0 => {
-                    Some(no_cards(&self.game.players[to].name))
-                }
-                TurnEvent::Took(quantity) => {
-                    Some(had_n_cards(&self.game.players[to].name, quantity, card))
+    /// Goes on from the last snapshot of the game, telling the chat the game is lost otherwise.
+    async fn recover(&mut self) -> bool {
+        let chat_id = self.session.chat_id();
+        self.restarts += 1;
+        let restored = if self.restarts > MAX_RESTARTS {
+            Err(anyhow!("it crashed {} times", self.restarts))
+        } else {
+            self.session.rollback()
+        };
+        let (recovered, message) = match restored {
+            Ok(()) => (true, GAME_RESTORED),
+            Err(err) => {
+                log::error!("Cannot restore the game of chat {}: {}", chat_id, err);
+                // Don't bring the broken game back when the bot restarts
+                if let Err(err) = self.session.discard() {
+                    log::error!("Cannot remove the game of chat {}: {}", chat_id, err);
                 }
-                TurnEvent::Group(card) => Some(made_group(&from.first_name, card)),
-                _ => None,
-            };
-            if let Some(msg) = msg {
-                cx.answer(msg).await?;
+                (false, GAME_CRASHED)
             }
+        };
+        if let Err(err) = self.bot.send_message(chat_id, message).await {
+            metrics::telegram_error(&err);
+            log::error!("Cannot tell chat {} about the crash: {}", chat_id, err);
         }
-        if let GameState::Drawing(_) = self.game.state {
-            self.draw(cx, card).await?;
+        recovered
+    }
+}
+
+impl GameActor {
+    /// Forgets the game after the registry dropped it, so it is not restored with the bot.
+    fn stop(&mut self) {
+        let chat_id = self.session.chat_id();
+        if let Err(err) = self.session.discard() {
+            log::error!("Cannot remove the game of chat {}: {}", chat_id, err);
         }
-        self.send_status_to_players(
-            &cx,
-            &vec![
-                self.game.players[to].clone(),
-                self.game.get_player_by_id(&player_id).unwrap().clone(),
-            ],
-        )
-        .await?;
-        self.check_game_state(&cx).await
+        self.stopped = true;
     }
 
-    async fn draw(&mut self, cx: &Cx, card: usize) -> Result<()> {
-        let from = cx.update.from().unwrap();
-        let events = self
-            .game
-            .execute(Action::Draw(format!("{}", from.id), card as u8))?;
-        for event in events {
-            let msg = match event {
-                TurnEvent::Drawn(drawn) if drawn == (card as u8) => {
-                    Some(drawn_expected_card(&from.first_name, drawn))
-                }
-                TurnEvent::Drawn(_) => Some(drawn_card(&from.first_name)),
-                TurnEvent::DeckEmpty => Some(EMPTY_DECK.into()),
-                TurnEvent::Group(card) => Some(made_group(&from.first_name, card)),
-                _ => None,
-            };
-            if let Some(msg) = msg {
-                cx.answer(msg).await?;
-            }
+    /// Lets the registry drop the game once it is over.
+    fn check_game_over(&self) {
+        if self.session.is_over() {
+            self.game_over.notify();
         }
-        Ok(())
     }
 
-    async fn status(&self, cx: &Cx) -> Result<()> {
-        cx.answer(game_status(&self.game.players, self.game.deck.len()))
-            .await?;
-        Ok(())
+    async fn handle_message(&mut self, Message(user, command): Message) -> Result<()> {
+        let response = self.session.handle(&Identity::from(&user), command);
+        self.deliver(response).await
     }
 
-    async fn check_game_state(&self, cx: &Cx) -> Result<()> {
-        match &self.game.state {
-            GameState::Asking(index) => {
-                cx.answer(ask_for_cards(
-                    &self.game.players[*index].name,
-                    &self.game.players,
-                ))
+    async fn handle_callback(&mut self, Callback(query, data): Callback) -> Result<()> {
+        let from = Identity::from(&query.from);
+        // Private keyboards could be pressed for the game of a chat the user doesn't play in
+        if self.session.game().get_player_by_id(&from.id).is_none() {
+            self.bot
+                .answer_callback_query(query.id)
+                .text(INVALID_PLAYER)
+                .show_alert(true)
                 .await?;
+            return Ok(());
+        }
+        let command = match data {
+            CallbackData::ChooseOpponent(_, to) => GameCommand::ChooseOpponent(to),
+            CallbackData::ChooseCard(_, to, card) => GameCommand::ChooseCard(to, card),
+        };
+        // The name of the target before the question changes the game
+        let chosen = match (&command, &self.session.game().state) {
+            (GameCommand::ChooseCard(to, card), GameState::Asking(_)) => self
+                .session
+                .game()
+                .players
+                .get(*to)
+                .map(|target| card_chosen(&target.name, *card)),
+            _ => None,
+        };
+        let Response { messages, error } = self.session.handle(&from, command);
+        let answer = self.bot.answer_callback_query(query.id);
+        match error {
+            None => {
+                answer.await?;
+                // Replace the private card keyboard with the question that was chosen
+                if let (Some(message), Some(chosen)) = (&query.message, chosen) {
+                    self.bot
+                        .edit_message_text(message.chat.id, message.id, chosen)
+                        .await?;
+                }
             }
-            GameState::GameOver(GameResults { winners, score }) => {
-                cx.answer(game_over(winners, *score)).await?;
+            Some(error) => {
+                answer.text(error).show_alert(true).await?;
             }
-            _ => {}
         }
-        Ok(())
+        self.deliver(Response {
+            messages,
+            error: None,
+        })
+        .await
     }
 
-    async fn send_status_to_players(&self, cx: &Cx, players: &[Player]) -> Result<()> {
-        let bot = &cx.requester;
-        for player in players {
-            bot.send_message(player.id.clone(), player_status(player))
-                .send()
-                .await?;
+    /// Sends the messages of the response and its error to the group.
+    async fn deliver(&self, response: Response) -> Result<()> {
+        let delivered = self.transport.deliver_all(&response.messages).await;
+        let mut error = response.error;
+        // Private messages fail when players haven't started the bot
+        if delivered.is_err() && error.is_none() {
+            error = Some(UNKNOWN_ERROR.into());
+        }
+        if let Some(error) = error {
+            self.bot.send_message(self.session.chat_id(), error).await?;
         }
         Ok(())
     }
 }
+
+impl GameActor {
+    pub fn new(bot: AutoSend<Bot>, session: GameSession, game_over: GameOverNotifier) -> Self {
+        let transport = TelegramTransport::new(bot.clone(), session.chat_id());
+        Self {
+            bot,
+            session,
+            transport,
+            restarts: 0,
+            game_over,
+            stopped: false,
+        }
+    }
+}
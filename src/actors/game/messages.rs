@@ -1,30 +1,14 @@
-use crate::{alias::Cx, command::Command};
-use tokio::sync::oneshot::Sender as Responder;
+use crate::{keyboards::CallbackData, session::messages::GameCommand};
+use teloxide::types::{CallbackQuery, User};
 
-#[derive(Debug)]
-pub enum GameCommand {
-    Start,
-    Join,
-    Status,
-    Ask(usize, usize),
-}
-
-impl From<Command> for GameCommand {
-    fn from(cmd: Command) -> Self {
-        match cmd {
-            Command::Join => GameCommand::Join,
-            Command::Start => GameCommand::Start,
-            Command::Ask { to, card } => GameCommand::Ask(to, card),
-            Command::Status => GameCommand::Status,
-            _ => panic!("Cannot convert Command to GameCommand"),
-        }
-    }
-}
-
-pub struct IsOver(pub Responder<bool>);
-pub struct Message(pub Cx, pub GameCommand);
+// Message(sender, command)
+pub struct Message(pub User, pub GameCommand);
+// Callback(query, data) of a pressed inline keyboard button
+pub struct Callback(pub CallbackQuery, pub CallbackData);
 
 pub enum GameActorMsg {
     Message(Message),
-    IsOver(IsOver),
+    Callback(Box<Callback>),
+    // The game was ended or dropped, forget it once the messages sent before are handled
+    Stop,
 }
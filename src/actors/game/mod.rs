@@ -1,2 +1,3 @@
+pub mod actor;
 pub mod messages;
-pub mod actor;
\ No newline at end of file
+pub mod registry;
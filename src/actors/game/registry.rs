@@ -0,0 +1,338 @@
+use super::messages::GameActorMsg;
+use crate::actors::MAILBOX_SIZE;
+use crate::storage::GameStore;
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Weak,
+};
+use std::time::Duration;
+use tokio::{
+    sync::mpsc::{self, Sender, UnboundedReceiver, UnboundedSender},
+    time::{self, Instant},
+};
+
+// Most time between two looks for idle games
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+struct GameEntry {
+    // Tells this actor apart from a later one in the same chat
+    id: u64,
+    sender: Sender<GameActorMsg>,
+    last_active: Instant,
+}
+
+/// Lets a game actor tell the registry that its game is over.
+#[derive(Clone)]
+pub struct GameOverNotifier {
+    chat_id: i64,
+    id: u64,
+    tx: UnboundedSender<(i64, u64)>,
+}
+
+impl GameOverNotifier {
+    pub fn notify(&self) {
+        let _ = self.tx.send((self.chat_id, self.id));
+    }
+}
+
+/// The game actors of every chat with a game.
+///
+/// Games are dropped once their actor says they are over, when their actor
+/// dies and after being idle for too long. Dropped actors are told to stop,
+/// they forget their game once they handled the messages sent before.
+pub struct GameRegistry {
+    games: DashMap<i64, GameEntry>,
+    store: Arc<dyn GameStore>,
+    idle_timeout: Duration,
+    finished: UnboundedSender<(i64, u64)>,
+    next_id: AtomicU64,
+}
+
+impl GameRegistry {
+    /// Creates an empty registry, forgetting games that get no message for `idle_timeout`.
+    pub fn new(store: Arc<dyn GameStore>, idle_timeout: Duration) -> Arc<Self> {
+        let (finished, rx) = mpsc::unbounded_channel();
+        let registry = Arc::new(Self {
+            games: DashMap::new(),
+            store,
+            idle_timeout,
+            finished,
+            next_id: AtomicU64::new(0),
+        });
+        tokio::spawn(Self::run(Arc::downgrade(&registry), rx, idle_timeout));
+        registry
+    }
+
+    /// Spawns the actor of a new game in the chat with `spawn`, unless the chat already has one.
+    ///
+    /// Returns whether the game was created.
+    pub fn create<F>(&self, chat_id: i64, spawn: F) -> bool
+    where
+        F: FnOnce(GameOverNotifier) -> Sender<GameActorMsg>,
+    {
+        if self.has_game(chat_id) {
+            return false;
+        }
+        // Spawned without holding the lock of the chat, there is no telling what `spawn` does
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let notifier = GameOverNotifier {
+            chat_id,
+            id,
+            tx: self.finished.clone(),
+        };
+        let game = GameEntry {
+            id,
+            sender: spawn(notifier),
+            last_active: Instant::now(),
+        };
+        match self.games.entry(chat_id) {
+            // Another game was created meanwhile, this actor stops once its sender is dropped
+            Entry::Occupied(entry) if !entry.get().sender.is_closed() => return false,
+            Entry::Occupied(mut entry) => {
+                entry.insert(game);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(game);
+            }
+        }
+        true
+    }
+
+    fn has_game(&self, chat_id: i64) -> bool {
+        self.games
+            .get(&chat_id)
+            .is_some_and(|game| !game.sender.is_closed())
+    }
+
+    /// Sender of the game actor of the chat, forgetting it if the actor is dead.
+    pub fn get(&self, chat_id: i64) -> Option<Sender<GameActorMsg>> {
+        let mut game = self.games.get_mut(&chat_id)?;
+        if game.sender.is_closed() {
+            drop(game);
+            log::warn!("The game actor of chat {} is dead", chat_id);
+            self.games.remove(&chat_id);
+            return None;
+        }
+        game.last_active = Instant::now();
+        Some(game.sender.clone())
+    }
+
+    /// Ends the game of the chat, returning whether there was one.
+    pub async fn remove(&self, chat_id: i64) -> bool {
+        match self.games.remove(&chat_id) {
+            Some((_, game)) => {
+                self.stop(chat_id, game.sender).await;
+                true
+            }
+            None => {
+                self.discard(chat_id);
+                false
+            }
+        }
+    }
+
+    /// Tells the actor to stop, forgetting the game here when the actor is already dead.
+    async fn stop(&self, chat_id: i64, sender: Sender<GameActorMsg>) {
+        if sender.send(GameActorMsg::Stop).await.is_err() {
+            self.discard(chat_id);
+        }
+    }
+
+    fn discard(&self, chat_id: i64) {
+        if let Err(err) = self.store.remove(chat_id) {
+            log::error!("Cannot remove the game of chat {}: {}", chat_id, err);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// Messages waiting in the mailbox of every game actor.
+    pub fn mailbox_depths(&self) -> Vec<usize> {
+        self.games
+            .iter()
+            .map(|game| MAILBOX_SIZE.saturating_sub(game.sender.capacity()))
+            .collect()
+    }
+
+    /// Whether games are still dropped when they are over or idle.
+    pub fn is_alive(&self) -> bool {
+        !self.finished.is_closed()
+    }
+
+    /// Drops the games that are over and the idle ones, as long as the registry lives.
+    async fn run(
+        registry: Weak<Self>,
+        mut finished: UnboundedReceiver<(i64, u64)>,
+        idle: Duration,
+    ) {
+        let mut interval = time::interval(idle.min(EVICTION_INTERVAL));
+        loop {
+            tokio::select! {
+                over = finished.recv() => match (over, registry.upgrade()) {
+                    (Some((chat_id, id)), Some(registry)) => {
+                        registry.games.remove_if(&chat_id, |_, game| game.id == id);
+                    }
+                    _ => break,
+                },
+                _ = interval.tick() => match registry.upgrade() {
+                    Some(registry) => registry.evict_idle().await,
+                    None => break,
+                },
+            }
+        }
+    }
+
+    async fn evict_idle(&self) {
+        self.games.retain(|_, game| !game.sender.is_closed());
+        let chats: Vec<i64> = self.games.iter().map(|game| *game.key()).collect();
+        for chat_id in chats {
+            let idle = |_: &i64, game: &GameEntry| game.last_active.elapsed() >= self.idle_timeout;
+            if let Some((_, game)) = self.games.remove_if(&chat_id, idle) {
+                log::info!("Dropping the idle game of chat {}", chat_id);
+                self.stop(chat_id, game.sender).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::{
+        game::{actor::GameActor, messages::Message},
+        run_async_actor,
+    };
+    use crate::entities::config::GameConfig;
+    use crate::session::{game::GameSession, messages::GameCommand};
+    use crate::storage::sqlite::SqliteStore;
+    use std::sync::Mutex;
+    use teloxide::{prelude::*, types::User};
+    use url::Url;
+
+    fn registry(idle_timeout: Duration) -> Arc<GameRegistry> {
+        GameRegistry::new(
+            Arc::new(SqliteStore::open(":memory:").unwrap()),
+            idle_timeout,
+        )
+    }
+
+    /// Creates a game whose actor only keeps its receiver, handing over the notifier.
+    fn create(registry: &GameRegistry, chat_id: i64) -> Option<GameOverNotifier> {
+        let notifier = Mutex::new(None);
+        let created = registry.create(chat_id, |game_over| {
+            *notifier.lock().unwrap() = Some(game_over);
+            let (tx, mut rx) = mpsc::channel(1);
+            tokio::spawn(async move { while rx.recv().await.is_some() {} });
+            tx
+        });
+        assert_eq!(created, notifier.lock().unwrap().is_some());
+        notifier.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn chats_have_a_single_game() {
+        let registry = registry(Duration::from_secs(60));
+        assert!(create(&registry, 1).is_some());
+        assert!(create(&registry, 1).is_none());
+        assert!(create(&registry, 2).is_some());
+        assert_eq!(registry.len(), 2);
+        assert!(registry.get(1).is_some());
+        assert!(registry.remove(1).await);
+        assert!(!registry.remove(1).await);
+        assert!(registry.get(1).is_none());
+        assert!(create(&registry, 1).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn games_are_dropped_when_their_actor_says_they_are_over() {
+        let registry = registry(Duration::from_secs(60));
+        let first = create(&registry, 1).unwrap();
+        first.notify();
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(registry.get(1).is_none());
+
+        // Late notifications don't drop the next game of the chat
+        create(&registry, 1).unwrap();
+        first.notify();
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(registry.get(1).is_some());
+    }
+
+    #[tokio::test]
+    async fn dead_actors_are_forgotten() {
+        let registry = registry(Duration::from_secs(60));
+        registry.create(1, |_| mpsc::channel(1).0);
+        assert!(registry.get(1).is_none());
+        assert!(registry.is_empty());
+        assert!(create(&registry, 1).is_some());
+    }
+
+    #[tokio::test]
+    async fn mailboxes_are_measured() {
+        let registry = registry(Duration::from_secs(60));
+        let (tx, _rx) = mpsc::channel(MAILBOX_SIZE);
+        registry.create(1, |_| tx);
+        assert_eq!(registry.mailbox_depths(), vec![0]);
+
+        // Reserved slots are taken like the messages waiting for the actor
+        let sender = registry.get(1).unwrap();
+        let waiting: Vec<_> = (0..3).map(|_| sender.try_reserve().unwrap()).collect();
+        assert_eq!(registry.mailbox_depths(), vec![3]);
+        drop(waiting);
+        assert_eq!(registry.mailbox_depths(), vec![0]);
+        assert!(registry.is_alive());
+    }
+
+    #[tokio::test]
+    async fn removed_games_are_not_saved_again() {
+        let store = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let registry = GameRegistry::new(store.clone(), Duration::from_secs(60));
+        // Nothing listens there, messages to the chat fail right away
+        let api = Url::parse("http://127.0.0.1:9").unwrap();
+        let bot = Bot::new("0:token").set_api_url(api).auto_send();
+        let session = GameSession::new(1, GameConfig::default(), store.clone(), store.clone());
+        registry.create(1, |game_over| {
+            run_async_actor(GameActor::new(bot, session, game_over))
+        });
+        let join = |id: i64| {
+            let user = User {
+                id,
+                is_bot: false,
+                first_name: format!("Player {}", id),
+                last_name: None,
+                username: None,
+                language_code: None,
+            };
+            GameActorMsg::Message(Message(user, GameCommand::Join))
+        };
+        let sender = registry.get(1).unwrap();
+        assert!(sender.send(join(1)).await.is_ok());
+        assert!(registry.remove(1).await);
+        // Sent by a handler that got the sender before the game was removed
+        let _ = sender.send(join(2)).await;
+        time::timeout(Duration::from_secs(10), sender.closed())
+            .await
+            .unwrap();
+        assert!(store.load_all().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_games_are_evicted() {
+        let registry = registry(Duration::from_millis(100));
+        create(&registry, 1).unwrap();
+        create(&registry, 2).unwrap();
+        for _ in 0..4 {
+            time::sleep(Duration::from_millis(50)).await;
+            registry.get(2);
+        }
+        assert!(registry.get(1).is_none());
+        assert!(registry.get(2).is_some());
+    }
+}
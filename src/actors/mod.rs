@@ -1,26 +1,188 @@
 pub mod game;
 
+use crate::metrics;
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::sync::mpsc::{self, Sender};
+use futures::FutureExt;
+use std::{
+    panic::AssertUnwindSafe,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{
+    sync::mpsc::{self, Sender},
+    time::{self, Instant},
+};
+
+/// Messages an actor can have waiting before its senders wait too.
+pub const MAILBOX_SIZE: usize = 32;
+
+// Times any actor has recovered from a panic since the bot started
+static RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+/// Times the actors have recovered from a panic since the bot started.
+pub fn restarts() -> u64 {
+    RESTARTS.load(Ordering::Relaxed)
+}
 
 #[async_trait]
 pub trait AsyncActor<T> {
     type Output;
 
     async fn handle(&mut self, cmd: T) -> Result<Self::Output>;
+
+    /// Instant at which the actor wants to be woken up if no message arrives before.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Called when the deadline is reached.
+    async fn on_deadline(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the actor is done, its task then ends and the messages still waiting are dropped.
+    fn is_stopped(&self) -> bool {
+        false
+    }
+
+    /// Called after the actor panicked, to get back to a good state.
+    ///
+    /// Returns whether the actor recovered, otherwise it is stopped and its
+    /// senders are closed.
+    async fn recover(&mut self) -> bool {
+        false
+    }
 }
 
+/// Spawns the actor, supervising it so a panic never kills its task silently.
 pub fn run_async_actor<T, E>(mut actor: E) -> Sender<T>
 where
     T: 'static + Send,
     E: 'static + AsyncActor<T> + Send,
 {
-    let (tx, mut rx) = mpsc::channel(32);
+    let (tx, mut rx) = mpsc::channel(MAILBOX_SIZE);
     tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            let _ = actor.handle(msg).await;
+        loop {
+            let handled = match actor.deadline() {
+                Some(deadline) => tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => supervise(actor.handle(msg)).await,
+                        None => break,
+                    },
+                    _ = time::sleep_until(deadline) => supervise(actor.on_deadline()).await,
+                },
+                None => match rx.recv().await {
+                    Some(msg) => supervise(actor.handle(msg)).await,
+                    None => break,
+                },
+            };
+            match handled {
+                Some(Ok(())) => {}
+                Some(Err(err)) => {
+                    metrics::error(&err);
+                    log::error!("An actor failed to handle a message: {}", err);
+                }
+                None => {
+                    let recovered = AssertUnwindSafe(actor.recover())
+                        .catch_unwind()
+                        .await
+                        .unwrap_or(false);
+                    if !recovered {
+                        log::error!("Stopping an actor that panicked and cannot recover");
+                        break;
+                    }
+                    let restarts = RESTARTS.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::warn!(
+                        "An actor recovered from a panic ({} restarts so far)",
+                        restarts
+                    );
+                }
+            }
+            if actor.is_stopped() {
+                break;
+            }
         }
     });
     tx
 }
+
+/// Runs a handler of the actor, returning `None` if it panicked.
+async fn supervise<F, O>(handler: F) -> Option<Result<()>>
+where
+    F: std::future::Future<Output = Result<O>>,
+{
+    AssertUnwindSafe(handler)
+        .catch_unwind()
+        .await
+        .ok()
+        .map(|result| result.map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    enum TestMsg {
+        Add(u32),
+        Panic,
+        Get(oneshot::Sender<u32>),
+    }
+
+    struct Counter {
+        count: u32,
+        recovers: bool,
+    }
+
+    #[async_trait]
+    impl AsyncActor<TestMsg> for Counter {
+        type Output = ();
+
+        async fn handle(&mut self, msg: TestMsg) -> Result<()> {
+            match msg {
+                TestMsg::Add(n) => self.count += n,
+                TestMsg::Panic => panic!("Counter panicked"),
+                TestMsg::Get(tx) => {
+                    let _ = tx.send(self.count);
+                }
+            }
+            Ok(())
+        }
+
+        async fn recover(&mut self) -> bool {
+            self.count = 0;
+            self.recovers
+        }
+    }
+
+    async fn get(sender: &Sender<TestMsg>) -> Option<u32> {
+        let (tx, rx) = oneshot::channel();
+        sender.send(TestMsg::Get(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    #[tokio::test]
+    async fn actors_recover_from_panics() {
+        let before = restarts();
+        let sender = run_async_actor(Counter {
+            count: 0,
+            recovers: true,
+        });
+        sender.send(TestMsg::Add(2)).await.ok().unwrap();
+        sender.send(TestMsg::Panic).await.ok().unwrap();
+        sender.send(TestMsg::Add(3)).await.ok().unwrap();
+        assert_eq!(get(&sender).await, Some(3));
+        assert!(restarts() > before);
+    }
+
+    #[tokio::test]
+    async fn actors_that_cannot_recover_are_stopped() {
+        let sender = run_async_actor(Counter {
+            count: 0,
+            recovers: false,
+        });
+        sender.send(TestMsg::Panic).await.ok().unwrap();
+        assert_eq!(get(&sender).await, None);
+        assert!(sender.is_closed());
+    }
+}
@@ -0,0 +1,44 @@
+use super::{random, Strategy};
+use crate::entities::{card::Rank, game::Game};
+use rand::RngCore;
+use std::collections::{HashMap, HashSet};
+
+/// Remembers which ranks every player has asked for.
+///
+/// Asking for a rank means having it, so whenever the bot holds a rank
+/// that someone asked for (and didn't give away since) it asks them for it.
+#[derive(Default)]
+pub struct MemoryStrategy {
+    known: HashMap<String, HashSet<Rank>>,
+}
+
+impl Strategy for MemoryStrategy {
+    fn choose(&mut self, game: &Game, me: usize, rng: &mut dyn RngCore) -> Option<(usize, Rank)> {
+        let hand = game.players.get(me)?.ranks();
+        for to in random::opponents(game, me) {
+            let known = match self.known.get(&game.players[to].id) {
+                Some(known) => known,
+                None => continue,
+            };
+            if let Some(&rank) = hand.iter().find(|rank| known.contains(rank)) {
+                return Some((to, rank));
+            }
+        }
+        random::random_move(game, me, rng)
+    }
+
+    fn observe_ask(&mut self, asker: &str, target: &str, rank: Rank, took: u8) {
+        self.known.entry(asker.into()).or_default().insert(rank);
+        if took > 0 {
+            if let Some(known) = self.known.get_mut(target) {
+                known.remove(&rank);
+            }
+        }
+    }
+
+    fn observe_group(&mut self, rank: Rank) {
+        for known in self.known.values_mut() {
+            known.remove(&rank);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+pub mod memory;
+pub mod random;
+
+use crate::entities::{card::Rank, game::Game, player::Difficulty};
+use memory::MemoryStrategy;
+use rand::RngCore;
+use random::RandomStrategy;
+
+/// Decides the moves of a computer controlled player.
+pub trait Strategy: Send + Sync {
+    /// Chooses who to ask and for which rank, `me` being the index of the bot,
+    /// taking chances only from `rng`.
+    /// Returns `None` when there is nothing sensible to ask for.
+    fn choose(&mut self, game: &Game, me: usize, rng: &mut dyn RngCore) -> Option<(usize, Rank)>;
+
+    /// Called after every question: `asker` asked `target` for `rank` and took `took` cards.
+    fn observe_ask(&mut self, _asker: &str, _target: &str, _rank: Rank, _took: u8) {}
+
+    /// Called every time someone makes a group of `rank`.
+    fn observe_group(&mut self, _rank: Rank) {}
+}
+
+pub fn strategy_for(difficulty: Difficulty) -> Box<dyn Strategy> {
+    match difficulty {
+        Difficulty::Easy => Box::new(RandomStrategy),
+        Difficulty::Hard => Box::new(MemoryStrategy::default()),
+    }
+}
@@ -0,0 +1,34 @@
+use super::Strategy;
+use crate::entities::{card::Rank, game::Game};
+use rand::{seq::SliceRandom, RngCore};
+
+/// Asks a random opponent for the rank of a random card of its hand.
+pub struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn choose(&mut self, game: &Game, me: usize, rng: &mut dyn RngCore) -> Option<(usize, Rank)> {
+        random_move(game, me, rng)
+    }
+}
+
+pub fn random_move(game: &Game, me: usize, rng: &mut dyn RngCore) -> Option<(usize, Rank)> {
+    let card = *game.players.get(me)?.cards.choose(rng)?;
+    let opponents = opponents(game, me);
+    let to = *opponents.choose(rng)?;
+    Some((to, card.rank))
+}
+
+/// Indexes of the other players, preferring the ones that still have cards.
+pub fn opponents(game: &Game, me: usize) -> Vec<usize> {
+    let others: Vec<usize> = (0..game.players.len()).filter(|&i| i != me).collect();
+    let with_cards: Vec<usize> = others
+        .iter()
+        .copied()
+        .filter(|&i| game.players[i].has_cards())
+        .collect();
+    if with_cards.is_empty() {
+        others
+    } else {
+        with_cards
+    }
+}
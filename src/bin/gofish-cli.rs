@@ -0,0 +1,214 @@
+use anyhow::Result;
+use gofish::command::Command;
+use gofish::entities::{config::GameConfig, game::GameState, history::GameExport};
+use gofish::session::{
+    game::GameSession,
+    messages::{GameCommand, Identity, Keyboard, Outbound, Recipient, Response},
+};
+use gofish::storage::{sqlite::SqliteStore, StatsStore};
+use gofish::templates::*;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use teloxide::utils::command::BotCommand;
+
+// Commands can be sent as /command@gofish, like in a group
+const BOT_NAME: &str = "gofish";
+// The terminal plays as a single chat
+const CHAT_ID: i64 = 1;
+const CLI_HELP: &str = "Terminal only:\n/as <name> - play as someone else, the seat changes by itself when it's a player's turn\n/load <file> [moves] - replay an exported game, up to the given number of moves\n/quit - exit";
+
+/// Plays games in the terminal, every player sitting in front of the same keyboard.
+struct Cli {
+    db: Arc<SqliteStore>,
+    session: Option<GameSession>,
+    seat: Identity,
+}
+
+impl Cli {
+    fn new(db: Arc<SqliteStore>) -> Self {
+        Self {
+            db,
+            session: None,
+            seat: identity("Player"),
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<()> {
+        match line.split_once(' ') {
+            Some(("/as", name)) => {
+                self.seat = identity(name.trim());
+                return Ok(());
+            }
+            Some(("/load", args)) => return self.load(args),
+            _ => {}
+        }
+        let command = match Command::parse(line, BOT_NAME) {
+            Ok(command) => command,
+            Err(err) => {
+                println!("{}\n\nSend /help to see the available commands", err);
+                return Ok(());
+            }
+        };
+        match command {
+            Command::Help => println!("{}\n\n{}", Command::descriptions(), CLI_HELP),
+            Command::NewGame { options } => {
+                let config = match options.parse::<GameConfig>() {
+                    Ok(config) => config,
+                    Err(err) => {
+                        println!("{}", invalid_options(&err));
+                        return Ok(());
+                    }
+                };
+                if self.session.is_some() {
+                    println!("{}", GAME_ALREADY_CREATED);
+                    return Ok(());
+                }
+                let rules = game_rules(&config);
+                let (store, stats) = (self.db.clone(), self.db.clone());
+                self.session = Some(GameSession::new(CHAT_ID, config, store, stats));
+                println!("{}\n\n{}", GAME_CREATED, rules);
+            }
+            Command::EndGame => match self.session.take() {
+                Some(_) => println!("{}", GAME_FINISHED),
+                None => println!("{}", NO_GAME_IN_PROGRESS),
+            },
+            Command::Stats => {
+                let stats = self.db.player_stats(&self.seat.id)?;
+                println!("{}", player_stats(&self.seat.name, &stats));
+            }
+            Command::Leaderboard { scope } => {
+                let global = scope.trim().eq_ignore_ascii_case("global");
+                let chat = if global { None } else { Some(CHAT_ID) };
+                println!("{}", leaderboard(&self.db.leaderboard(chat, 10)?, global));
+            }
+            command => match (
+                self.session.as_mut(),
+                GameCommand::from_command(command, None),
+            ) {
+                (Some(session), Some(command)) => {
+                    let response = session.handle(&self.seat, command);
+                    self.show(response);
+                }
+                (None, _) => println!("{}", NO_GAME_CREATED),
+                (Some(_), None) => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Replays an exported game and keeps playing from there.
+    fn load(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let path = args.next().unwrap_or_default();
+        let export: GameExport = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let moves = match args.next() {
+            Some(moves) => moves.parse()?,
+            None => export.moves.len(),
+        };
+        let game = export.replay(moves)?;
+        let (store, stats) = (self.db.clone(), self.db.clone());
+        self.session = Some(GameSession::restore(CHAT_ID, game, store, stats));
+        println!("Replayed {} moves\n", moves.min(export.moves.len()));
+        let response = self
+            .session
+            .as_mut()
+            .unwrap()
+            .handle(&self.seat, GameCommand::Status);
+        self.show(response);
+        Ok(())
+    }
+
+    /// Prints the response and hands the keyboard to whoever has to play.
+    fn show(&mut self, Response { messages, error }: Response) {
+        let session = match self.session.as_ref() {
+            Some(session) => session,
+            None => return,
+        };
+        let game = session.game();
+        for message in messages {
+            match message {
+                Outbound::Message(to, text, keyboard) => {
+                    if let Recipient::Player(id) = to {
+                        let name = game.get_player_by_id(&id).map_or(id.as_str(), |p| &p.name);
+                        println!("🔒 Only for {}:", name);
+                    }
+                    println!("{}", text);
+                    match keyboard {
+                        Some(Keyboard::Opponents(opponents)) => {
+                            let names: Vec<String> = opponents
+                                .iter()
+                                .map(|(i, name)| format!("{}) {}", i, name))
+                                .collect();
+                            println!("[{}]", names.join(" | "));
+                        }
+                        Some(Keyboard::Ranks(_, ranks)) => {
+                            let ranks: Vec<String> = ranks.iter().map(|r| r.to_string()).collect();
+                            println!("[{}]", ranks.join(" | "));
+                        }
+                        None => {}
+                    }
+                }
+                Outbound::Document(_, file_name, contents) => match fs::write(&file_name, contents)
+                {
+                    Ok(()) => println!("📎 Saved {}", file_name),
+                    Err(err) => println!("⚠️ Cannot save {}: {}", file_name, err),
+                },
+                Outbound::Dice => println!("🎲"),
+            }
+            println!();
+        }
+        if let Some(error) = error {
+            println!("⚠️ {}\n", error);
+        }
+        if let GameState::Asking(index) = game.state {
+            let player = &game.players[index];
+            if !player.is_bot() {
+                self.seat = Identity {
+                    id: player.id.clone(),
+                    name: player.name.clone(),
+                    username: player.username.clone(),
+                };
+            }
+        }
+        if session.is_over() {
+            self.session = None;
+        }
+    }
+}
+
+/// Players are known by their name in the terminal.
+fn identity(name: &str) -> Identity {
+    Identity {
+        id: name.to_lowercase(),
+        name: name.into(),
+        username: Some(name.to_lowercase()),
+    }
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+    let db = Arc::new(SqliteStore::open(":memory:")?);
+    let mut cli = Cli::new(db);
+    println!("Go Fish! Send /help to see the commands.\n");
+    let stdin = io::stdin();
+    loop {
+        print!("{}> ", cli.seat.name);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "/quit" => break,
+            _ => {
+                if let Err(err) = cli.execute(line) {
+                    println!("⚠️ {}\n", err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use teloxide::utils::command::BotCommand;
+use crate::entities::player::Difficulty;
+use teloxide::utils::command::{BotCommand, ParseError};
 
 // Derive BotCommand to parse text with a command into this enumeration.
 //
@@ -16,18 +17,86 @@ use teloxide::utils::command::BotCommand;
     parse_with = "split"
 )]
 pub enum Command {
-    #[command(description = "create a new game")]
-    NewGame,
+    #[command(
+        description = "create a new game, options: suddendeath hand=<1-10> ranks=<1-13> group=<2|4> showdrawn=<on|off> fishagain=<on|off> timeout=<seconds> seed=<number> (admins only)",
+        parse_with = "default"
+    )]
+    NewGame { options: String },
     #[command(description = "join the current game")]
     Join,
+    #[command(description = "add a computer player to the game (easy or hard)")]
+    AddBot { difficulty: Difficulty },
     #[command(description = "start the game")]
     Start,
+    #[command(description = "leave the game")]
+    Leave,
+    #[command(
+        description = "kick a player out of the game by name, @username or replying to them (admins only)",
+        parse_with = "default"
+    )]
+    Kick { player: String },
+    #[command(description = "get the seed to replay the game privately (admins only)")]
+    Seed,
+    #[command(
+        description = "show the last moves of the game, 10 by default",
+        parse_with = "default"
+    )]
+    History { moves: String },
+    #[command(
+        description = "get the game as JSON to replay it, privately while it is being played (admins only)"
+    )]
+    Export,
     #[command(description = "end the game")]
     EndGame,
-    #[command(description = "ask someone for cards")]
-    Ask { to: usize, card: usize },
+    #[command(
+        description = "ask someone for cards by name, @username or replying to them",
+        parse_with = "parse_ask"
+    )]
+    Ask { to: String, card: String },
     #[command(description = "ask the bot to show the game general status")]
     Status,
+    #[command(description = "show your statistics, or the ones of the player you reply to")]
+    Stats,
+    #[command(
+        description = "show the best rated players of this chat, or of every chat with /leaderboard global",
+        parse_with = "default"
+    )]
+    Leaderboard { scope: String },
     #[command(description = "Show bot commands")]
     Help,
 }
+
+impl Command {
+    /// Name of the command as players type it, without the slash.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::NewGame { .. } => "newgame",
+            Command::Join => "join",
+            Command::AddBot { .. } => "addbot",
+            Command::Start => "start",
+            Command::Leave => "leave",
+            Command::Kick { .. } => "kick",
+            Command::Seed => "seed",
+            Command::History { .. } => "history",
+            Command::Export => "export",
+            Command::EndGame => "endgame",
+            Command::Ask { .. } => "ask",
+            Command::Status => "status",
+            Command::Stats => "stats",
+            Command::Leaderboard { .. } => "leaderboard",
+            Command::Help => "help",
+        }
+    }
+}
+
+/// Splits `/ask <player> <card>` taking the last word as the card, so names
+/// can have spaces and the player can be left out when replying to them.
+fn parse_ask(input: String) -> Result<(String, String), ParseError> {
+    let mut words: Vec<&str> = input.split_whitespace().collect();
+    let card = words.pop().ok_or_else(|| ParseError::TooFewArguments {
+        expected: 2,
+        found: 0,
+        message: "Expected the card to ask for".into(),
+    })?;
+    Ok((words.join(" "), card.into()))
+}
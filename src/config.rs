@@ -0,0 +1,667 @@
+use crate::entities::config::GameConfig;
+use crate::session::game::Expiry;
+use crate::webhook::IpRange;
+use serde::Deserialize;
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+use url::Url;
+
+// File read when no other is given, if it exists
+const CONFIG_FILE: &str = "gofish.toml";
+const DATABASE_PATH: &str = "gofish.db";
+// Port the webhook listens on when no address is given, one of those Telegram posts to
+const WEBHOOK_PORT: u16 = 8443;
+// Minutes to start a game and minutes a started game can go without anybody playing
+const LOBBY_TIMEOUT: u64 = 60;
+const IDLE_TIMEOUT: u64 = 24 * 60;
+
+/// Every problem found in the configuration.
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid configuration:\n  {}", .0.join("\n  "))]
+pub struct ConfigError(pub Vec<String>);
+
+/// How the bot gets its updates from Telegram.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdateMode {
+    // Telegram posts them to the warp server, which needs a public host
+    Webhook,
+    // The bot asks for them, which works anywhere
+    Polling,
+}
+
+impl FromStr for UpdateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "webhook" => Ok(UpdateMode::Webhook),
+            "polling" => Ok(UpdateMode::Polling),
+            _ => Err(format!("'{}' is not webhook or polling", s)),
+        }
+    }
+}
+
+/// Certificate and private key to serve the webhook over HTTPS, both PEM encoded.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // Upload the certificate to Telegram, which doesn't trust it otherwise
+    pub self_signed: bool,
+}
+
+/// Where the webhook listens and what it trusts.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WebhookConfig {
+    // Public URL Telegram posts the updates to
+    pub url: Url,
+    // Address the server listens on
+    pub bind: SocketAddr,
+    // Path of the URL, a single segment
+    pub path: String,
+    // Secret Telegram sends back with every update, random if not set
+    pub secret: Option<String>,
+    // Addresses updates may come from, any if empty
+    pub allowed_ips: Vec<IpRange>,
+    // Take the address of the sender from the X-Forwarded-For header added by a proxy
+    pub behind_proxy: bool,
+    pub tls: Option<TlsConfig>,
+}
+
+/// Where the updates come from.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Updates {
+    Webhook(Box<WebhookConfig>),
+    Polling,
+}
+
+impl Updates {
+    pub fn mode(&self) -> UpdateMode {
+        match self {
+            Updates::Webhook(_) => UpdateMode::Webhook,
+            Updates::Polling => UpdateMode::Polling,
+        }
+    }
+}
+
+/// Settings of the bot, read once when it starts.
+#[derive(Clone)]
+pub struct Config {
+    pub token: String,
+    pub updates: Updates,
+    pub database_path: PathBuf,
+    pub expiry: Expiry,
+    // Rules of the games, before the options given to /newgame
+    pub default_rules: GameConfig,
+    // Users who can administrate the games of every chat
+    pub admins: Vec<i64>,
+    // Address serving the health checks and metrics, kept off the public webhook
+    pub status_address: Option<SocketAddr>,
+}
+
+/// Contents of the configuration file, every setting being optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    token: Option<String>,
+    mode: Option<String>,
+    database_path: Option<PathBuf>,
+    lobby_timeout: Option<u64>,
+    idle_timeout: Option<u64>,
+    default_rules: Option<String>,
+    admins: Option<Vec<i64>>,
+    status_address: Option<SocketAddr>,
+    webhook: FileWebhookConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileWebhookConfig {
+    host: Option<String>,
+    port: Option<u16>,
+    bind: Option<SocketAddr>,
+    path: Option<String>,
+    secret: Option<String>,
+    allowed_ips: Option<Vec<IpRange>>,
+    behind_proxy: Option<bool>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    self_signed: Option<bool>,
+}
+
+impl Config {
+    /// Reads the configuration from the environment, `.env` and the configuration file.
+    ///
+    /// The file is the one given with `--config`, `CONFIG_FILE` or `gofish.toml` if it exists.
+    /// Environment variables take precedence over the file, and `--webhook` or `--polling`
+    /// over both.
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+        let mut args = env::args().skip(1);
+        let mut mode = None;
+        let mut path = env::var("CONFIG_FILE").ok();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--webhook" => mode = Some(UpdateMode::Webhook),
+                "--polling" => mode = Some(UpdateMode::Polling),
+                "--config" => path = args.next(),
+                _ => {}
+            }
+        }
+        let file = match path {
+            Some(path) => Some(
+                fs::read_to_string(&path)
+                    .map_err(|err| ConfigError(vec![format!("Cannot read {}: {}", path, err)]))?,
+            ),
+            None => fs::read_to_string(CONFIG_FILE).ok(),
+        };
+        Self::parse(file.as_deref(), |key| env::var(key).ok(), mode)
+    }
+
+    /// Builds the configuration from the contents of a file and the variables found by `var`,
+    /// which take precedence, unless `mode` says how to get the updates.
+    pub fn parse<F>(
+        file: Option<&str>,
+        var: F,
+        mode: Option<UpdateMode>,
+    ) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut loader = Loader {
+            var,
+            errors: vec![],
+        };
+        let mut file: FileConfig = match file.map(toml::from_str).transpose() {
+            Ok(file) => file.unwrap_or_default(),
+            Err(err) => {
+                loader
+                    .errors
+                    .push(format!("Invalid configuration file: {}", err));
+                FileConfig::default()
+            }
+        };
+
+        let token = loader
+            .value::<String>("TELOXIDE_TOKEN", file.token)
+            .filter(|token| !token.is_empty());
+        if token.is_none() {
+            loader.errors.push("TELOXIDE_TOKEN is missing".into());
+        }
+        let file_mode = file.mode.map(|mode| mode.parse()).transpose();
+        let file_mode = file_mode.unwrap_or_else(|err| {
+            loader.errors.push(format!("mode {}", err));
+            None
+        });
+        let mode = mode.or_else(|| loader.value("UPDATE_MODE", file_mode));
+        let host = loader.value::<String>("HOST", file.webhook.host.take());
+        let updates = match mode {
+            Some(UpdateMode::Webhook) => loader.webhook(host, token.as_deref(), file.webhook),
+            Some(UpdateMode::Polling) => Updates::Polling,
+            None if host.is_some() => loader.webhook(host, token.as_deref(), file.webhook),
+            None => Updates::Polling,
+        };
+
+        let database_path = loader
+            .value("DATABASE_PATH", file.database_path)
+            .unwrap_or_else(|| DATABASE_PATH.into());
+        let lobby = loader.value("LOBBY_TIMEOUT", file.lobby_timeout);
+        let idle = loader.value("IDLE_TIMEOUT", file.idle_timeout);
+        let expiry = Expiry {
+            lobby: minutes(lobby.unwrap_or(LOBBY_TIMEOUT)),
+            idle: minutes(idle.unwrap_or(IDLE_TIMEOUT)),
+        };
+        let default_rules = loader
+            .value::<String>("DEFAULT_RULES", file.default_rules)
+            .map(|rules| GameConfig::default().with_options(&rules))
+            .transpose()
+            .and_then(|rules| match rules {
+                Some(GameConfig { seed: Some(_), .. }) => {
+                    Err("every game would be dealt the same cards with a seed".into())
+                }
+                rules => Ok(rules.unwrap_or_default()),
+            })
+            .unwrap_or_else(|err| {
+                loader.errors.push(format!("DEFAULT_RULES: {}", err));
+                GameConfig::default()
+            });
+        let admins = loader.list("ADMIN_IDS", file.admins);
+        let status_address = loader.value("STATUS_ADDRESS", file.status_address);
+
+        match (token, loader.errors.is_empty()) {
+            (Some(token), true) => Ok(Config {
+                token,
+                updates,
+                database_path,
+                expiry,
+                default_rules,
+                admins,
+                status_address,
+            }),
+            _ => Err(ConfigError(loader.errors)),
+        }
+    }
+}
+
+/// A number of minutes, 0 meaning forever.
+fn minutes(minutes: u64) -> Option<Duration> {
+    match minutes {
+        0 => None,
+        minutes => Some(Duration::from_secs(minutes * 60)),
+    }
+}
+
+/// Takes the settings from the variables or the file, keeping every error found.
+struct Loader<F> {
+    var: F,
+    errors: Vec<String>,
+}
+
+impl<F> Loader<F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    /// The variable `key`, unless it is missing or blank as in `.env.example`.
+    fn var(&self, key: &str) -> Option<String> {
+        (self.var)(key).filter(|value| !value.trim().is_empty())
+    }
+
+    /// The value of the variable `key` if it is set, otherwise the one in the file.
+    fn value<T>(&mut self, key: &str, file: Option<T>) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.var(key) {
+            Some(value) => match value.trim().parse() {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    self.errors.push(format!("{} '{}': {}", key, value, err));
+                    None
+                }
+            },
+            None => file,
+        }
+    }
+
+    /// Like `value` for a comma separated list.
+    fn list<T>(&mut self, key: &str, file: Option<Vec<T>>) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = match self.var(key) {
+            Some(value) => value,
+            None => return file.unwrap_or_default(),
+        };
+        let mut list = vec![];
+        for item in value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match item.parse() {
+                Ok(item) => list.push(item),
+                Err(err) => self.errors.push(format!("{} '{}': {}", key, item, err)),
+            }
+        }
+        list
+    }
+
+    /// Like `value` for a switch, which can be on, yes, true or 1.
+    fn switch(&mut self, key: &str, file: Option<bool>) -> bool {
+        match self.var(key) {
+            Some(value) => match value.trim().to_lowercase().as_str() {
+                "1" | "on" | "yes" | "true" => true,
+                "0" | "off" | "no" | "false" => false,
+                _ => {
+                    self.errors
+                        .push(format!("{} '{}' is not on or off", key, value));
+                    false
+                }
+            },
+            None => file.unwrap_or_default(),
+        }
+    }
+
+    fn webhook(
+        &mut self,
+        host: Option<String>,
+        token: Option<&str>,
+        file: FileWebhookConfig,
+    ) -> Updates {
+        let port = self.value("PORT", file.port).unwrap_or(WEBHOOK_PORT);
+        let bind = self
+            .value("BIND_ADDRESS", file.bind)
+            .unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, port).into());
+        // The token keeps strangers from guessing the path
+        let path = self
+            .value::<String>("WEBHOOK_PATH", file.path)
+            .map(|path| path.trim_matches('/').to_string())
+            .unwrap_or_else(|| format!("bot{}", token.unwrap_or_default()));
+        if path.is_empty() || path.contains('/') {
+            self.errors
+                .push(format!("WEBHOOK_PATH '{}' must be a single segment", path));
+        }
+        let url = match host {
+            Some(host) => Url::parse(&format!("https://{}/{}", host, path))
+                .map_err(|err| self.errors.push(format!("HOST '{}': {}", host, err)))
+                .ok(),
+            None => {
+                self.errors.push("HOST is needed by the webhook".into());
+                None
+            }
+        };
+
+        let secret = self.value::<String>("WEBHOOK_SECRET", file.secret);
+        // Telegram only accepts these characters
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if let Some(secret) = &secret {
+            if secret.is_empty() || secret.len() > 256 || !secret.chars().all(valid) {
+                self.errors
+                    .push("WEBHOOK_SECRET must have 1 to 256 letters, numbers, _ or -".into());
+            }
+        }
+        let allowed_ips = self.list("WEBHOOK_ALLOWED_IPS", file.allowed_ips);
+        let behind_proxy = self.switch("WEBHOOK_BEHIND_PROXY", file.behind_proxy);
+
+        let cert = self.value::<PathBuf>("TLS_CERT", file.tls_cert);
+        let key = self.value::<PathBuf>("TLS_KEY", file.tls_key);
+        let self_signed = self.switch("WEBHOOK_SELF_SIGNED", file.self_signed);
+        let tls = match (cert, key) {
+            (Some(cert), Some(key)) => {
+                for (name, path) in [("TLS_CERT", &cert), ("TLS_KEY", &key)].iter() {
+                    if !path.is_file() {
+                        let path = path.display();
+                        self.errors
+                            .push(format!("{} '{}' is not a file", name, path));
+                    }
+                }
+                Some(TlsConfig {
+                    cert,
+                    key,
+                    self_signed,
+                })
+            }
+            (None, None) if self_signed => {
+                self.errors
+                    .push("WEBHOOK_SELF_SIGNED needs TLS_CERT and TLS_KEY".into());
+                None
+            }
+            (None, None) => None,
+            _ => {
+                self.errors
+                    .push("TLS_CERT and TLS_KEY must be given together".into());
+                None
+            }
+        };
+
+        match url {
+            Some(url) => Updates::Webhook(Box::new(WebhookConfig {
+                url,
+                bind,
+                path,
+                secret,
+                allowed_ips,
+                behind_proxy,
+                tls,
+            })),
+            None => Updates::Polling,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::config::Variant;
+    use std::collections::HashMap;
+
+    fn parse(file: &str, vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        parse_as(file, vars, None)
+    }
+
+    fn parse_as(
+        file: &str,
+        vars: &[(&str, &str)],
+        mode: Option<UpdateMode>,
+    ) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::parse(Some(file), |key| vars.get(key).cloned(), mode)
+    }
+
+    fn webhook(config: &Config) -> &WebhookConfig {
+        match &config.updates {
+            Updates::Webhook(webhook) => webhook,
+            Updates::Polling => panic!("The bot is polling"),
+        }
+    }
+
+    #[test]
+    fn only_the_token_is_needed() {
+        let config = parse("", &[("TELOXIDE_TOKEN", "123:abc")]).unwrap();
+        assert_eq!(config.token, "123:abc");
+        assert_eq!(config.updates, Updates::Polling);
+        assert_eq!(config.database_path, PathBuf::from(DATABASE_PATH));
+        assert_eq!(config.expiry.lobby, Some(Duration::from_secs(60 * 60)));
+        assert_eq!(config.expiry.idle, Some(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(config.default_rules, GameConfig::default());
+        assert!(config.admins.is_empty());
+        assert_eq!(config.status_address, None);
+    }
+
+    #[test]
+    fn a_host_means_a_webhook() {
+        let vars = [("TELOXIDE_TOKEN", "123:abc"), ("HOST", "fish.example.com")];
+        let config = parse("", &vars).unwrap();
+        let webhook = webhook(&config);
+        assert_eq!(webhook.url.as_str(), "https://fish.example.com/bot123:abc");
+        assert_eq!(webhook.path, "bot123:abc");
+        assert_eq!(webhook.bind, "0.0.0.0:8443".parse().unwrap());
+        assert_eq!(webhook.secret, None);
+        assert!(!webhook.behind_proxy);
+
+        let config = parse_as("", &vars, Some(UpdateMode::Polling)).unwrap();
+        assert_eq!(config.updates.mode(), UpdateMode::Polling);
+    }
+
+    #[test]
+    fn the_file_is_read() {
+        let file = r#"
+            token = "123:abc"
+            mode = "webhook"
+            database_path = "/data/gofish.db"
+            lobby_timeout = 0
+            idle_timeout = 30
+            default_rules = "suddendeath group=2"
+            admins = [42, 7]
+            status_address = "127.0.0.1:9090"
+
+            [webhook]
+            host = "fish.example.com:88"
+            bind = "127.0.0.1:8080"
+            path = "/updates"
+            secret = "s3cret"
+            allowed_ips = ["149.154.160.0/20", "91.108.4.0/22"]
+            behind_proxy = true
+        "#;
+        let config = parse(file, &[]).unwrap();
+        assert_eq!(config.database_path, PathBuf::from("/data/gofish.db"));
+        assert_eq!(config.expiry.lobby, None);
+        assert_eq!(config.expiry.idle, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(config.default_rules.variant, Variant::SuddenDeath);
+        assert_eq!(config.default_rules.group_size, 2);
+        assert_eq!(config.admins, vec![42, 7]);
+        assert_eq!(
+            config.status_address,
+            Some("127.0.0.1:9090".parse().unwrap())
+        );
+        let webhook = webhook(&config);
+        assert_eq!(webhook.url.as_str(), "https://fish.example.com:88/updates");
+        assert_eq!(webhook.bind, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(webhook.secret.as_deref(), Some("s3cret"));
+        assert_eq!(webhook.allowed_ips.len(), 2);
+        assert!(webhook.behind_proxy);
+    }
+
+    #[test]
+    fn variables_take_precedence_over_the_file() {
+        let file = r#"
+            token = "123:abc"
+            admins = [42]
+            [webhook]
+            host = "fish.example.com"
+            port = 443
+        "#;
+        let vars = [
+            ("PORT", "8080"),
+            ("ADMIN_IDS", "1, 2"),
+            ("DATABASE_PATH", "fish.db"),
+            ("WEBHOOK_BEHIND_PROXY", "yes"),
+        ];
+        let config = parse(file, &vars).unwrap();
+        assert_eq!(config.admins, vec![1, 2]);
+        assert_eq!(config.database_path, PathBuf::from("fish.db"));
+        let webhook = webhook(&config);
+        assert_eq!(webhook.bind, "0.0.0.0:8080".parse().unwrap());
+        assert!(webhook.behind_proxy);
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let vars = [
+            ("UPDATE_MODE", "webhook"),
+            ("PORT", "http"),
+            ("LOBBY_TIMEOUT", "-1"),
+            ("DEFAULT_RULES", "seed=1"),
+            ("ADMIN_IDS", "42,alice"),
+            ("WEBHOOK_SECRET", "not secret"),
+            ("WEBHOOK_ALLOWED_IPS", "10.0.0.0/33"),
+            ("WEBHOOK_BEHIND_PROXY", "maybe"),
+            ("TLS_CERT", "/nowhere/cert.pem"),
+        ];
+        let ConfigError(errors) = parse("", &vars).err().unwrap();
+        for key in [
+            "TELOXIDE_TOKEN",
+            "PORT",
+            "HOST",
+            "LOBBY_TIMEOUT",
+            "DEFAULT_RULES",
+            "ADMIN_IDS",
+            "WEBHOOK_SECRET",
+            "WEBHOOK_ALLOWED_IPS",
+            "WEBHOOK_BEHIND_PROXY",
+            "TLS_CERT and TLS_KEY",
+        ]
+        .iter()
+        {
+            assert!(
+                errors.iter().any(|error| error.starts_with(key)),
+                "{} in {:?}",
+                key,
+                errors
+            );
+        }
+        assert_eq!(errors.len(), 10);
+    }
+
+    #[test]
+    fn the_example_variables_are_valid() {
+        let vars: Vec<(&str, &str)> = include_str!("../.env.example")
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim().trim_matches('"')))
+            .collect();
+        let with_token = |token| -> Vec<(&str, &str)> {
+            vars.iter()
+                .map(|&(key, value)| match key {
+                    "TELOXIDE_TOKEN" => (key, token),
+                    _ => (key, value),
+                })
+                .collect()
+        };
+        // Blank variables are left unset
+        let ConfigError(errors) = parse("", &with_token("")).err().unwrap();
+        assert_eq!(errors, ["TELOXIDE_TOKEN is missing"]);
+        let config = parse("", &with_token("123:abc")).unwrap();
+        assert_eq!(config.updates, Updates::Polling);
+        assert_eq!(config.database_path, PathBuf::from("gofish.db"));
+    }
+
+    #[test]
+    fn the_example_file_is_valid() {
+        let file = include_str!("../gofish.toml.example");
+        let config = parse(file, &[("TELOXIDE_TOKEN", "123:abc")]).unwrap();
+        assert_eq!(config.updates, Updates::Polling);
+        let config = parse(
+            file,
+            &[("TELOXIDE_TOKEN", "123:abc"), ("UPDATE_MODE", "webhook")],
+        );
+        assert_eq!(webhook(&config.unwrap()).allowed_ips.len(), 2);
+    }
+
+    #[test]
+    fn invalid_files_are_reported() {
+        let vars = [("TELOXIDE_TOKEN", "123:abc")];
+        for file in ["token = ", "colour = \"red\"", "[webhook]\nport = \"http\""].iter() {
+            let ConfigError(errors) = parse(file, &vars).err().unwrap();
+            assert!(errors[0].starts_with("Invalid configuration file"));
+        }
+        let ConfigError(errors) = parse("mode = \"carrier pigeon\"", &vars).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["mode 'carrier pigeon' is not webhook or polling"]
+        );
+    }
+
+    #[test]
+    fn tls_files_must_exist() {
+        let dir = env::temp_dir();
+        let cert = dir.join("gofish-test-cert.pem");
+        fs::write(&cert, "").unwrap();
+        let cert = cert.to_str().unwrap();
+        let vars = [
+            ("TELOXIDE_TOKEN", "123:abc"),
+            ("HOST", "fish.example.com"),
+            ("TLS_CERT", cert),
+            ("TLS_KEY", cert),
+        ];
+        let config = parse("", &vars).unwrap();
+        let tls = webhook(&config).tls.clone().unwrap();
+        assert_eq!(tls.cert, PathBuf::from(cert));
+        assert!(!tls.self_signed);
+
+        let self_signed = [
+            vars[0],
+            vars[1],
+            vars[2],
+            vars[3],
+            ("WEBHOOK_SELF_SIGNED", "1"),
+        ];
+        let config = parse("", &self_signed).unwrap();
+        assert!(webhook(&config).tls.as_ref().unwrap().self_signed);
+
+        let vars = [vars[0], vars[1], vars[2], ("TLS_KEY", "/nowhere/key.pem")];
+        let ConfigError(errors) = parse("", &vars).err().unwrap();
+        assert_eq!(errors, vec!["TLS_KEY '/nowhere/key.pem' is not a file"]);
+
+        let vars = [vars[0], vars[1], ("WEBHOOK_SELF_SIGNED", "true")];
+        let ConfigError(errors) = parse("", &vars).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["WEBHOOK_SELF_SIGNED needs TLS_CERT and TLS_KEY"]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Rank of a card, from the Ace (1) to the King (13).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Rank(u8);
+
+impl Rank {
+    pub const ACE: Rank = Rank(1);
+    pub const KING: Rank = Rank(13);
+
+    pub fn new(value: u8) -> Option<Rank> {
+        if (Rank::ACE.0..=Rank::KING.0).contains(&value) {
+            Some(Rank(value))
+        } else {
+            None
+        }
+    }
+
+    /// The first `count` ranks, starting from the Ace.
+    pub fn first(count: u8) -> impl Iterator<Item = Rank> {
+        (Rank::ACE.0..=count.min(Rank::KING.0)).map(Rank)
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "A"),
+            11 => write!(f, "J"),
+            12 => write!(f, "Q"),
+            13 => write!(f, "K"),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+impl FromStr for Rank {
+    type Err = String;
+
+    /// Parses ranks as written by the players: "A", "ace", "7", "10", "q", "queen"...
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rank = match s.trim().to_lowercase().as_str() {
+            "a" | "ace" => Some(Rank::ACE),
+            "j" | "jack" => Rank::new(11),
+            "q" | "queen" => Rank::new(12),
+            "k" | "king" => Some(Rank::KING),
+            number => number.parse().ok().and_then(Rank::new),
+        };
+        rank.ok_or_else(|| format!("{} is not a card", s))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades,
+}
+
+impl Suit {
+    pub const ALL: [Suit; 4] = [Suit::Clubs, Suit::Diamonds, Suit::Hearts, Suit::Spades];
+}
+
+impl fmt::Display for Suit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Suit::Clubs => "♣️",
+            Suit::Diamonds => "♦️",
+            Suit::Hearts => "♥️",
+            Suit::Spades => "♠️",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Card {
+    pub rank: Rank,
+    pub suit: Suit,
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.rank, self.suit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_are_parsed_as_players_write_them() {
+        let parse = |s: &str| s.parse::<Rank>().ok().map(|r| r.value());
+        assert_eq!(parse("A"), Some(1));
+        assert_eq!(parse("ace"), Some(1));
+        assert_eq!(parse(" 7 "), Some(7));
+        assert_eq!(parse("10"), Some(10));
+        assert_eq!(parse("J"), Some(11));
+        assert_eq!(parse("Queen"), Some(12));
+        assert_eq!(parse("k"), Some(13));
+        for invalid in ["", "0", "14", "-1", "joker", "1.5"].iter() {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn ranks_are_displayed_the_way_they_are_parsed() {
+        for rank in Rank::first(13) {
+            assert_eq!(rank.to_string().parse::<Rank>(), Ok(rank));
+        }
+        assert_eq!(Rank::ACE.to_string(), "A");
+        assert_eq!(Rank::KING.to_string(), "K");
+    }
+
+    #[test]
+    fn there_are_thirteen_ranks() {
+        assert_eq!(Rank::new(0), None);
+        assert_eq!(Rank::new(14), None);
+        assert_eq!(Rank::first(20).count(), 13);
+        assert_eq!(Rank::first(0).count(), 0);
+    }
+}
@@ -0,0 +1,222 @@
+use super::card::Rank;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Ranks of a full deck, from the Ace to the King.
+pub const MAX_RANKS: u8 = 13;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Variant {
+    // Players with an empty hand refill it from the deck,
+    // the game ends once every group has been made
+    Standard,
+    // The game ends as soon as someone runs out of cards
+    SuddenDeath,
+}
+
+/// Rules of a game, chosen when it is created.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct GameConfig {
+    pub variant: Variant,
+    // Cards dealt to every player, by default it depends on the number of players
+    pub hand_size: Option<u8>,
+    // The deck has the four suits of the first `ranks` ranks, starting from the Ace
+    pub ranks: u8,
+    // Cards needed to make a group, 2 or 4
+    pub group_size: u8,
+    // Tell everyone which card was drawn
+    pub show_drawn: bool,
+    // Drawing the card you asked for lets you keep asking
+    pub fish_again: bool,
+    // Seconds a player has to ask before the bot plays for them, 0 to wait forever
+    pub turn_timeout: u32,
+    // Seed to shuffle with, random by default
+    pub seed: Option<u64>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            variant: Variant::Standard,
+            hand_size: None,
+            ranks: MAX_RANKS,
+            group_size: 4,
+            show_drawn: false,
+            fish_again: true,
+            turn_timeout: 120,
+            seed: None,
+        }
+    }
+}
+
+impl GameConfig {
+    /// Cards dealt to every player when the game starts with `players` players.
+    pub fn hand_size(&self, players: usize) -> usize {
+        match self.hand_size {
+            Some(size) => size as usize,
+            None if players <= 3 => 7,
+            None => 5,
+        }
+    }
+
+    pub fn valid_rank(&self, rank: Rank) -> bool {
+        rank.value() <= self.ranks
+    }
+
+    /// Changes the rules with the options given to `/newgame`, e.g. `suddendeath hand=5 group=2`.
+    pub fn with_options(self, options: &str) -> Result<Self, String> {
+        let mut config = self;
+        for option in options.split_whitespace() {
+            let option = option.to_lowercase();
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, value),
+                None => ("rules", option.as_str()),
+            };
+            match key {
+                "rules" => config.variant = parse_variant(value)?,
+                "hand" => config.hand_size = Some(parse_number(key, value, 1, 10)?),
+                "ranks" => config.ranks = parse_number(key, value, 1, MAX_RANKS)?,
+                "group" => match value {
+                    "2" => config.group_size = 2,
+                    "4" => config.group_size = 4,
+                    _ => return Err(format!("Groups can be of 2 or 4 cards, not '{}'", value)),
+                },
+                "showdrawn" => config.show_drawn = parse_switch(key, value)?,
+                "fishagain" => config.fish_again = parse_switch(key, value)?,
+                "timeout" => config.turn_timeout = parse_timeout(value)?,
+                "seed" => {
+                    config.seed =
+                        Some(value.parse().map_err(|_| {
+                            format!("seed must be a positive number, not '{}'", value)
+                        })?)
+                }
+                _ => return Err(format!("Unknown game option '{}'", option)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl FromStr for GameConfig {
+    type Err = String;
+
+    /// Parses the options given to `/newgame` on top of the default rules.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GameConfig::default().with_options(s)
+    }
+}
+
+fn parse_variant(value: &str) -> Result<Variant, String> {
+    match value {
+        "standard" => Ok(Variant::Standard),
+        "suddendeath" => Ok(Variant::SuddenDeath),
+        _ => Err(format!(
+            "Unknown rules '{}', use standard or suddendeath",
+            value
+        )),
+    }
+}
+
+fn parse_number(key: &str, value: &str, min: u8, max: u8) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!("{} must be a number from {} to {}", key, min, max)),
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(seconds) if seconds == 0 || (30..=3600).contains(&seconds) => Ok(seconds),
+        _ => Err("timeout must be 0 (no timeout) or from 30 to 3600 seconds".into()),
+    }
+}
+
+fn parse_switch(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "yes" | "true" => Ok(true),
+        "off" | "no" | "false" => Ok(false),
+        _ => Err(format!("{} must be on or off", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_options_are_the_default_rules() {
+        assert_eq!("".parse::<GameConfig>(), Ok(GameConfig::default()));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let config: GameConfig =
+            "SuddenDeath hand=5 ranks=6 group=2 showdrawn=on fishagain=no timeout=0 seed=42"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            config,
+            GameConfig {
+                variant: Variant::SuddenDeath,
+                hand_size: Some(5),
+                ranks: 6,
+                group_size: 2,
+                show_drawn: true,
+                fish_again: false,
+                turn_timeout: 0,
+                seed: Some(42),
+            }
+        );
+        assert!(config.valid_rank(Rank::new(6).unwrap()));
+        assert!(!config.valid_rank(Rank::new(7).unwrap()));
+    }
+
+    #[test]
+    fn options_change_the_given_rules() {
+        let rules: GameConfig = "suddendeath group=2".parse().unwrap();
+        let config = rules.clone().with_options("hand=3").unwrap();
+        assert_eq!(
+            config,
+            GameConfig {
+                hand_size: Some(3),
+                ..rules
+            }
+        );
+        assert_eq!(
+            config.with_options("standard").unwrap().variant,
+            Variant::Standard
+        );
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        for options in [
+            "casual",
+            "hand=0",
+            "hand=11",
+            "ranks=14",
+            "group=3",
+            "showdrawn=maybe",
+            "timeout=10",
+            "seed=-1",
+            "colour=red",
+        ]
+        .iter()
+        {
+            assert!(options.parse::<GameConfig>().is_err(), "{}", options);
+        }
+    }
+
+    #[test]
+    fn hands_are_smaller_with_more_players() {
+        let config = GameConfig::default();
+        assert_eq!(config.hand_size(3), 7);
+        assert_eq!(config.hand_size(4), 5);
+        let config = GameConfig {
+            hand_size: Some(9),
+            ..config
+        };
+        assert_eq!(config.hand_size(6), 9);
+    }
+}
@@ -1,19 +1,20 @@
+use super::card::{Card, Rank, Suit};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::Serialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Deck {
-    cards: Vec<u8>,
+    cards: Vec<Card>,
 }
 
 impl Deck {
-    pub fn new() -> Self {
+    /// A deck with every suit of the first `ranks` ranks.
+    pub fn new(ranks: u8) -> Self {
         Self {
-            cards: vec![
-                1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7,
-                8, 8, 8, 8, 9, 9, 9, 9, 10, 10, 10, 10, 11, 11, 11, 11, 12, 12, 12, 12,
-            ],
+            cards: Rank::first(ranks)
+                .flat_map(|rank| Suit::ALL.iter().map(move |&suit| Card { rank, suit }))
+                .collect(),
         }
     }
 
@@ -21,24 +22,61 @@ impl Deck {
         self.cards.len()
     }
 
-    pub fn valid_card(card: u8) -> bool {
-        card >= 1 && card <= 12
+    pub fn is_empty(&self) -> bool {
+        self.cards.is_empty()
     }
 
-    pub fn shuffle(&mut self) {
-        let mut rng = thread_rng();
-        self.cards.shuffle(&mut rng);
+    pub fn put_back(&mut self, cards: &[Card]) {
+        self.cards.extend(cards);
     }
 
-    pub fn draw_n(&mut self, n: usize) -> Vec<u8> {
+    pub fn shuffle<R: Rng>(&mut self, rng: &mut R) {
+        self.cards.shuffle(rng);
+    }
+
+    pub fn draw_n(&mut self, n: usize) -> Vec<Card> {
         let mut result = vec![];
         for _ in 0..n {
-            let elem = self.cards.pop();
-            if let None = elem {
-                break;
+            match self.cards.pop() {
+                Some(card) => result.push(card),
+                None => break,
             }
-            result.push(elem.unwrap());
         }
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn decks_have_every_card_once() {
+        let mut deck = Deck::new(13);
+        deck.shuffle(&mut ChaCha8Rng::seed_from_u64(1));
+        let cards = deck.draw_n(100);
+        assert_eq!(cards.len(), 52);
+        assert_eq!(cards.iter().collect::<HashSet<_>>().len(), 52);
+        assert!(deck.is_empty());
+    }
+
+    #[test]
+    fn short_decks_only_have_the_first_ranks() {
+        let mut deck = Deck::new(3);
+        assert_eq!(deck.len(), 12);
+        assert!(deck.draw_n(12).iter().all(|c| c.rank.value() <= 3));
+    }
+
+    #[test]
+    fn cards_put_back_are_drawn_first() {
+        let mut deck = Deck::new(2);
+        let drawn = deck.draw_n(3);
+        assert_eq!(deck.len(), 5);
+        deck.put_back(&drawn);
+        assert_eq!(deck.len(), 8);
+        assert_eq!(deck.draw_n(1), vec![drawn[2]]);
+    }
+}
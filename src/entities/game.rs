@@ -1,39 +1,81 @@
-use crate::entities::{deck::Deck, player::Player};
+use crate::entities::{
+    card::{Card, Rank},
+    config::{GameConfig, Variant},
+    deck::Deck,
+    history::{GameExport, Move},
+    player::{Difficulty, Player},
+};
 use crate::errors::ActionError::*;
 use anyhow::Result;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::Serialize;
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    mem,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum TurnEvent {
     Started,
     Joined,
-    // Group(card)
-    Group(u8),
+    // Group(rank)
+    Group(Rank),
     // Drawn(card)
-    Drawn(u8),
+    Drawn(Card),
     // Took(quantity)
     Took(u8),
     DeckEmpty,
+    // Refilled(player index): the player ran out of cards and drew one
+    Refilled(usize),
+    // SatOut(player index): the player ran out of cards and the deck is empty
+    SatOut(usize),
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum Action {
     Start,
-    // Join(id, name)
-    Join(String, String),
-    // Take(player, to, card)
-    Ask(String, usize, u8),
-    // Draw(player, last_card)
-    Draw(String, u8),
+    // Join(id, name, username, bot difficulty)
+    Join(String, String, Option<String>, Option<Difficulty>),
+    // Take(player, to, rank)
+    Ask(String, usize, Rank),
+    // Draw(player, asked rank)
+    Draw(String, Rank),
+    // Leave(player)
+    Leave(String),
+    // Close(reason): the game was closed before it could end
+    Close(CloseReason),
 }
 
-#[derive(Serialize, PartialEq, Clone, Debug)]
+/// Why a game was closed before it ended.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum CloseReason {
+    // Nobody started the game in time
+    LobbyExpired,
+    // Nobody played for too long
+    Inactive,
+}
+
+/// Ways of referring to another player.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Target {
+    // Id of the player, e.g. the author of a replied message
+    Id(String),
+    // Option number, @username or (part of) the name of the player
+    Query(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct GameResults {
     pub winners: Vec<String>,
+    // Ids of the winners, as players can share a name
+    #[serde(default)]
+    pub winner_ids: Vec<String>,
     pub score: u8,
 }
 
-#[derive(Serialize, PartialEq, Clone, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub enum GameState {
     Waiting,
     Asking(usize),
@@ -41,19 +83,68 @@ pub enum GameState {
     GameOver(GameResults),
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Game {
     pub deck: Deck,
     pub state: GameState,
     pub players: Vec<Player>,
+    // Players who left once the game started, who lose it whatever their score
+    #[serde(default)]
+    pub departed: Vec<Player>,
+    #[serde(default)]
+    pub config: GameConfig,
+    // Unix time in seconds when the game started
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    // Seed of the random number generator, the same seed and actions replay the same game.
+    // Unknown for the games saved before it was stored
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default = "ChaCha8Rng::from_entropy")]
+    rng: ChaCha8Rng,
+    // Chooses the moves made for the players, apart from `rng` so replays don't depend on it
+    #[serde(default = "ChaCha8Rng::from_entropy")]
+    moves_rng: ChaCha8Rng,
+    // Every action executed so far along with its events
+    #[serde(default)]
+    pub history: Vec<Move>,
 }
 
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(config: GameConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
         Self {
-            deck: Deck::new(),
+            deck: Deck::new(config.ranks),
             state: GameState::Waiting,
             players: vec![],
+            departed: vec![],
+            config,
+            started_at: None,
+            seed: Some(seed),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            moves_rng: moves_rng(seed),
+            history: vec![],
+        }
+    }
+
+    /// Calls `choose` with the generator of the moves made for the players,
+    /// such as the bots, so the same seed makes them choose the same moves.
+    pub fn with_moves_rng<T, F>(&mut self, choose: F) -> T
+    where
+        F: FnOnce(&Game, &mut ChaCha8Rng) -> T,
+    {
+        let mut rng = mem::replace(&mut self.moves_rng, ChaCha8Rng::seed_from_u64(0));
+        let chosen = choose(self, &mut rng);
+        self.moves_rng = rng;
+        chosen
+    }
+
+    /// The seed and history of the game, enough to replay it.
+    pub fn export(&self) -> GameExport {
+        GameExport {
+            config: self.config.clone(),
+            seed: self.seed,
+            moves: self.history.clone(),
         }
     }
 
@@ -61,17 +152,85 @@ impl Game {
         self.players.iter().find(|p| p.id == id)
     }
 
+    /// Finds the index of the player the target refers to.
+    ///
+    /// Queries are tried as an option number, then as an @username and then
+    /// as the name of the player: exact, the start of it or any part of it.
+    pub fn find_player(&self, target: &Target) -> Result<usize> {
+        let query = match target {
+            Target::Id(id) => {
+                return self
+                    .get_player_index(id)
+                    .ok_or_else(|| UnknownPlayer(id.clone()).into())
+            }
+            Target::Query(query) => query.trim(),
+        };
+        if query.is_empty() {
+            return Err(UnknownPlayer(query.into()).into());
+        }
+        if let Ok(index) = query.parse::<usize>() {
+            if self.is_valid_player_index(index) {
+                return Ok(index);
+            }
+        }
+        let lowercase = query.to_lowercase();
+        if let Some(username) = lowercase.strip_prefix('@') {
+            return self
+                .players
+                .iter()
+                .position(|p| {
+                    p.username.as_ref().map(|u| u.to_lowercase()).as_deref() == Some(username)
+                })
+                .ok_or_else(|| UnknownPlayer(query.into()).into());
+        }
+        let name_matchers: [&dyn Fn(&str) -> bool; 3] = [
+            &|name| name == lowercase,
+            &|name| name.starts_with(&lowercase),
+            &|name| name.contains(&lowercase),
+        ];
+        for matches in name_matchers.iter() {
+            let found: Vec<usize> = (0..self.players.len())
+                .filter(|&i| matches(&self.players[i].name.to_lowercase()))
+                .collect();
+            match found.as_slice() {
+                [] => continue,
+                [index] => return Ok(*index),
+                _ => {
+                    let names = found
+                        .iter()
+                        .map(|&i| self.players[i].name.clone())
+                        .collect();
+                    return Err(AmbiguousPlayer(query.into(), names).into());
+                }
+            }
+        }
+        Err(UnknownPlayer(query.into()).into())
+    }
+
+    /// Executes the action and appends it to the history of the game.
     pub fn execute(&mut self, action: Action) -> Result<Vec<TurnEvent>> {
-        let events = match action {
+        let events = match action.clone() {
             Action::Start => self.start_game()?,
-            Action::Join(id, name) => self.join_player(&id, &name)?,
+            Action::Join(id, name, username, bot) => self.join_player(&id, &name, username, bot)?,
             Action::Ask(id, to, card) => self.ask_to(id, to, card)?,
             Action::Draw(id, last_card) => self.draw_card(id, last_card)?,
+            Action::Leave(id) => self.leave(&id)?,
+            Action::Close(_) => self.close(),
         };
+        self.history.push(Move {
+            action,
+            events: events.clone(),
+        });
         Ok(events)
     }
 
-    fn join_player(&mut self, player_id: &str, name: &str) -> Result<Vec<TurnEvent>> {
+    fn join_player(
+        &mut self,
+        player_id: &str,
+        name: &str,
+        username: Option<String>,
+        bot: Option<Difficulty>,
+    ) -> Result<Vec<TurnEvent>> {
         if self.has_started() {
             return Err(GameAlreadyStarted.into());
         }
@@ -84,6 +243,10 @@ impl Game {
             score: 0,
             name: name.into(),
             id: player_id.into(),
+            username,
+            bot,
+            asks: 0,
+            fishes: 0,
         });
         Ok(vec![TurnEvent::Joined])
     }
@@ -92,16 +255,34 @@ impl Game {
         if self.has_started() {
             return Err(GameAlreadyStarted.into());
         }
-        self.deck.shuffle();
-        self.shuffle_players();
+        if self.players.len() < 2 {
+            return Err(NotEnoughPlayers.into());
+        }
+        self.deck.shuffle(&mut self.rng);
+        self.players.shuffle(&mut self.rng);
+        self.started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_secs());
+        // Never deal more cards than the deck has
+        let hand_size = self
+            .config
+            .hand_size(self.players.len())
+            .min(self.deck.len() / self.players.len());
         for player in &mut self.players {
-            player.cards.extend(self.deck.draw_n(7));
+            player.cards.extend(self.deck.draw_n(hand_size));
+        }
+        // Hands might already have groups with small groups or short decks
+        for player in &mut self.players {
+            player.reduce_groups(self.config.group_size);
         }
         self.state = GameState::Asking(0);
-        return Ok(vec![TurnEvent::Started]);
+        let mut events = vec![TurnEvent::Started];
+        self.check_turn(&mut events);
+        Ok(events)
     }
 
-    pub fn ask_to(&mut self, player_id: String, to: usize, card: u8) -> Result<Vec<TurnEvent>> {
+    pub fn ask_to(&mut self, player_id: String, to: usize, rank: Rank) -> Result<Vec<TurnEvent>> {
         let mut events = vec![];
         let index = self
             .get_player_index(&player_id)
@@ -109,16 +290,17 @@ impl Game {
         if !self.can_ask(index) {
             return Err(CannotAsk(player_id.clone()).into());
         }
-        if !self.is_valid_question(index, to, card) {
-            return Err(InvalidQuestion(to, card).into());
+        if !self.is_valid_question(index, to, rank) {
+            return Err(InvalidQuestion(to, rank).into());
         }
-        let cards = self.take_cards_from(to, card);
+        let cards = self.take_cards_from(to, rank);
         events.push(TurnEvent::Took(cards.len() as u8));
         let player = &mut self.players[index];
+        player.asks = player.asks.saturating_add(1);
         // Set player state to drawing if no cards were taken
-        if cards.len() > 0 {
+        if !cards.is_empty() {
             player.add_cards(&cards);
-            let groups = player.reduce_groups();
+            let groups = player.reduce_groups(self.config.group_size);
             // Group player cards
             for group in groups {
                 events.push(TurnEvent::Group(group))
@@ -127,14 +309,12 @@ impl Game {
             self.state = GameState::Drawing(index);
         }
 
-        if !player.has_cards() || !self.players[to].has_cards() {
-            self.game_over();
-        }
+        self.check_turn(&mut events);
 
         Ok(events)
     }
 
-    pub fn draw_card(&mut self, player_id: String, chosen_card: u8) -> Result<Vec<TurnEvent>> {
+    pub fn draw_card(&mut self, player_id: String, chosen_rank: Rank) -> Result<Vec<TurnEvent>> {
         let mut events = vec![];
         let index = self
             .get_player_index(&player_id)
@@ -143,47 +323,146 @@ impl Game {
             return Err(CannotDraw(player_id.clone()).into());
         }
         let player = &mut self.players[index];
+        player.fishes = player.fishes.saturating_add(1);
         let drawn = self.deck.draw_n(1);
-        if drawn.len() == 0 {
+        if drawn.is_empty() {
             events.push(TurnEvent::DeckEmpty);
         }
 
         if let Some(&card) = drawn.first() {
             player.add_cards(&drawn);
             events.push(TurnEvent::Drawn(card));
-            let groups = player.reduce_groups();
-            for card in groups {
-                events.push(TurnEvent::Group(card));
+            let groups = player.reduce_groups(self.config.group_size);
+            for rank in groups {
+                events.push(TurnEvent::Group(rank));
             }
-            if card != chosen_card {
+            if card.rank != chosen_rank || !self.config.fish_again {
                 self.end_turn();
             } else {
                 self.state = GameState::Asking(index);
-                if !player.has_cards() {
-                    self.game_over();
-                }
             }
         } else {
             self.end_turn();
         }
 
+        self.check_turn(&mut events);
+
         Ok(events)
     }
 
-    fn end_turn(&mut self) {
-        let index = match self.state {
-            GameState::Drawing(index) => index,
-            _ => panic!("Cannot end the turn"),
+    /// Takes the player out of the game, shuffling their cards back into the deck.
+    ///
+    /// Once the game has started the player is kept among the departed, so it still
+    /// counts as a loss, and the game is over if less than two players remain.
+    fn leave(&mut self, player_id: &str) -> Result<Vec<TurnEvent>> {
+        if let GameState::GameOver(_) = self.state {
+            return Err(GameIsOver.into());
+        }
+        let index = self
+            .get_player_index(player_id)
+            .ok_or_else(|| InvalidPlayerId(player_id.into()))?;
+        let mut player = self.players.remove(index);
+        self.deck.put_back(&player.cards);
+        self.deck.shuffle(&mut self.rng);
+        let mut events = vec![];
+        if self.state == GameState::Waiting {
+            return Ok(events);
+        }
+        player.cards.clear();
+        self.departed.push(player);
+        if self.players.len() < 2 {
+            self.game_over();
+            return Ok(events);
+        }
+        // Whoever comes after the player that left moves down a place
+        let shift = |turn: usize| if turn > index { turn - 1 } else { turn };
+        self.state = match self.state {
+            // The turn passes on to the next player, who is now at the same place
+            GameState::Asking(turn) | GameState::Drawing(turn) if turn == index => {
+                GameState::Asking(turn % self.players.len())
+            }
+            GameState::Asking(turn) => GameState::Asking(shift(turn)),
+            GameState::Drawing(turn) => GameState::Drawing(shift(turn)),
+            ref state => state.clone(),
         };
-        let new_index = (index + 1) % self.players.len();
-        self.state = GameState::Asking(new_index);
+        self.check_turn(&mut events);
+        Ok(events)
+    }
+
+    /// Ends the game as it is, without winners if it never started.
+    fn close(&mut self) -> Vec<TurnEvent> {
+        match self.state {
+            GameState::Waiting => {
+                self.state = GameState::GameOver(GameResults {
+                    winners: vec![],
+                    winner_ids: vec![],
+                    score: 0,
+                })
+            }
+            GameState::Asking(_) | GameState::Drawing(_) => self.game_over(),
+            GameState::GameOver(_) => {}
+        }
+        vec![]
+    }
+
+    /// Ends the game when the rules say so.
+    ///
+    /// With sudden death that is as soon as someone runs out of cards. With the standard
+    /// rules that is once every group is made, otherwise it makes sure the player asking
+    /// has cards, refilling their hand from the deck or passing the turn on when the deck is empty.
+    fn check_turn(&mut self, events: &mut Vec<TurnEvent>) {
+        if self.config.variant == Variant::SuddenDeath {
+            if self.players.iter().any(|p| !p.has_cards()) {
+                self.game_over();
+            }
+            return;
+        }
+        // Every card ends up in a group once the deck and all the hands are empty
+        if self.deck.is_empty() && self.players.iter().all(|p| !p.has_cards()) {
+            self.game_over();
+            return;
+        }
+        for _ in 0..self.players.len() {
+            let index = match self.state {
+                GameState::Asking(index) => index,
+                _ => return,
+            };
+            let player = &mut self.players[index];
+            if player.has_cards() {
+                return;
+            }
+            let drawn = self.deck.draw_n(1);
+            if drawn.is_empty() {
+                events.push(TurnEvent::SatOut(index));
+                self.state = GameState::Asking((index + 1) % self.players.len());
+            } else {
+                player.add_cards(&drawn);
+                events.push(TurnEvent::Refilled(index));
+                return;
+            }
+        }
+        // Nobody has cards left to play with
+        self.game_over();
+    }
+
+    /// Passes the turn on to the next player, once the player drawing is done.
+    fn end_turn(&mut self) {
+        debug_assert!(
+            matches!(self.state, GameState::Drawing(_)),
+            "Ending a turn in {:?}",
+            self.state
+        );
+        if let GameState::Drawing(index) = self.state {
+            self.state = GameState::Asking((index + 1) % self.players.len());
+        }
     }
 
     fn game_over(&mut self) {
         let winners = self.get_winners();
         self.state = GameState::GameOver(GameResults {
             winners: winners.iter().map(|p| p.name.clone()).collect(),
-            score: winners[0].score,
+            winner_ids: winners.iter().map(|p| p.id.clone()).collect(),
+            score: winners.first().map_or(0, |p| p.score),
         });
     }
 
@@ -191,18 +470,19 @@ impl Game {
         self.state != GameState::Waiting
     }
 
+    /// Players with the best score.
+    ///
+    /// Nobody wins if nobody made a group, unless everyone else left.
     fn get_winners(&self) -> Vec<&Player> {
-        let player = self.players.iter().max_by_key(|p| p.score).unwrap();
-        self.players
-            .iter()
-            .filter(|p| p.score == player.score)
-            .collect()
+        let score = match self.players.iter().map(|p| p.score).max() {
+            Some(score) if score > 0 || self.players.len() == 1 => score,
+            _ => return vec![],
+        };
+        self.players.iter().filter(|p| p.score == score).collect()
     }
 
-    fn take_cards_from(&mut self, from: usize, card: u8) -> Vec<u8> {
-        let from = &mut self.players[from];
-        let taken = from.remove_cards(card);
-        taken
+    fn take_cards_from(&mut self, from: usize, rank: Rank) -> Vec<Card> {
+        self.players[from].remove_cards(rank)
     }
 
     fn can_draw(&self, index: usize) -> bool {
@@ -213,21 +493,17 @@ impl Game {
         self.state == GameState::Asking(index)
     }
 
-    fn is_valid_question(&self, from: usize, to: usize, card: u8) -> bool {
+    fn is_valid_question(&self, from: usize, to: usize, rank: Rank) -> bool {
         self.is_valid_player_index(to)
-            && Deck::valid_card(card)
-            && self.players[from].cards.iter().any(|&c| c == card)
+            && from != to
+            && self.config.valid_rank(rank)
+            && self.players[from].has_rank(rank)
     }
 
     fn is_valid_player_index(&self, to: usize) -> bool {
         to < self.players.len()
     }
 
-    fn shuffle_players(&mut self) {
-        let mut rng = thread_rng();
-        self.players.shuffle(&mut rng);
-    }
-
     fn get_player_index(&self, player_id: &str) -> Option<usize> {
         self.players
             .iter()
@@ -236,3 +512,473 @@ impl Game {
             .map(|(index, _)| index)
     }
 }
+
+/// Generator of the moves chosen for the players in a game with the seed, one stream
+/// apart from the cards.
+fn moves_rng(seed: u64) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(1);
+    rng
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::card::Suit;
+    use crate::errors::ActionError;
+
+    fn rank(value: u8) -> Rank {
+        Rank::new(value).unwrap()
+    }
+
+    /// Cards of the given ranks, going through the suits so repeated ranks are different cards.
+    fn cards(ranks: &[u8]) -> Vec<Card> {
+        ranks
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Card {
+                rank: rank(value),
+                suit: Suit::ALL[i % Suit::ALL.len()],
+            })
+            .collect()
+    }
+
+    fn join(game: &mut Game, id: &str) -> Result<Vec<TurnEvent>> {
+        let name = id.to_uppercase();
+        game.execute(Action::Join(id.into(), name, Some(id.into()), None))
+    }
+
+    /// A started game where player `i` has `hands[i]` and the deck has `deck`,
+    /// the last card being the first one drawn. Players are named a, b, c...
+    fn setup(config: GameConfig, hands: &[&[u8]], deck: &[u8]) -> Game {
+        let mut game = Game::new(config);
+        for (i, hand) in hands.iter().enumerate() {
+            let id = ((b'a' + i as u8) as char).to_string();
+            join(&mut game, &id).unwrap();
+            game.players[i].cards = cards(hand);
+        }
+        game.deck = Deck::new(0);
+        game.deck.put_back(&cards(deck));
+        game.state = GameState::Asking(0);
+        game.history.clear();
+        game
+    }
+
+    fn error(result: Result<Vec<TurnEvent>>) -> ActionError {
+        result.err().unwrap().downcast::<ActionError>().unwrap()
+    }
+
+    fn sudden_death() -> GameConfig {
+        GameConfig {
+            variant: Variant::SuddenDeath,
+            ..GameConfig::default()
+        }
+    }
+
+    #[test]
+    fn players_cannot_join_twice() {
+        let mut game = Game::new(GameConfig::default());
+        join(&mut game, "a").unwrap();
+        let err = error(join(&mut game, "a"));
+        assert!(matches!(err, ActionError::PlayerAlreadyJoined(name) if name == "A"));
+    }
+
+    #[test]
+    fn games_need_two_players_to_start() {
+        let mut game = Game::new(GameConfig::default());
+        assert!(matches!(
+            error(game.execute(Action::Start)),
+            ActionError::NotEnoughPlayers
+        ));
+        join(&mut game, "a").unwrap();
+        assert!(matches!(
+            error(game.execute(Action::Start)),
+            ActionError::NotEnoughPlayers
+        ));
+    }
+
+    #[test]
+    fn games_cannot_be_joined_or_started_once_started() {
+        let mut game = Game::new(GameConfig::default());
+        join(&mut game, "a").unwrap();
+        join(&mut game, "b").unwrap();
+        game.execute(Action::Start).unwrap();
+        assert!(matches!(
+            error(join(&mut game, "c")),
+            ActionError::GameAlreadyStarted
+        ));
+        assert!(matches!(
+            error(game.execute(Action::Start)),
+            ActionError::GameAlreadyStarted
+        ));
+    }
+
+    #[test]
+    fn starting_deals_the_hands() {
+        let mut game = Game::new(GameConfig::default());
+        join(&mut game, "a").unwrap();
+        join(&mut game, "b").unwrap();
+        let events = game.execute(Action::Start).unwrap();
+        assert!(matches!(events[0], TurnEvent::Started));
+        assert_eq!(game.state, GameState::Asking(0));
+        // Groups made with the dealt cards are already scored
+        for player in &game.players {
+            assert_eq!(player.cards.len() + player.score as usize * 4, 7);
+        }
+        assert_eq!(game.deck.len(), 52 - 14);
+        assert!(game.started_at.is_some());
+    }
+
+    #[test]
+    fn hands_are_never_bigger_than_the_deck_allows() {
+        let config = GameConfig {
+            ranks: 2,
+            hand_size: Some(10),
+            ..GameConfig::default()
+        };
+        let mut game = Game::new(config);
+        for id in ["a", "b", "c"].iter() {
+            join(&mut game, id).unwrap();
+        }
+        game.execute(Action::Start).unwrap();
+        let dealt: usize = game
+            .players
+            .iter()
+            .map(|p| p.cards.len() + p.score as usize * 4)
+            .sum();
+        assert_eq!(dealt, 6);
+        assert_eq!(game.deck.len(), 2);
+    }
+
+    #[test]
+    fn only_players_can_ask_or_draw() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        let err = error(game.execute(Action::Ask("z".into(), 1, rank(1))));
+        assert!(matches!(err, ActionError::InvalidPlayerId(id) if id == "z"));
+        let err = error(game.execute(Action::Draw("z".into(), rank(1))));
+        assert!(matches!(err, ActionError::InvalidPlayerId(id) if id == "z"));
+        let err = error(game.execute(Action::Leave("z".into())));
+        assert!(matches!(err, ActionError::InvalidPlayerId(_)));
+    }
+
+    #[test]
+    fn players_only_ask_on_their_turn() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        let err = error(game.execute(Action::Ask("b".into(), 0, rank(2))));
+        assert!(matches!(err, ActionError::CannotAsk(id) if id == "b"));
+    }
+
+    #[test]
+    fn players_only_draw_after_a_failed_question() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        let err = error(game.execute(Action::Draw("a".into(), rank(1))));
+        assert!(matches!(err, ActionError::CannotDraw(id) if id == "a"));
+    }
+
+    #[test]
+    fn questions_must_be_valid() {
+        let config = GameConfig {
+            ranks: 5,
+            ..GameConfig::default()
+        };
+        let mut game = setup(config, &[&[1, 6], &[2]], &[3]);
+        // A card the player doesn't have
+        let err = error(game.execute(Action::Ask("a".into(), 1, rank(2))));
+        assert!(matches!(err, ActionError::InvalidQuestion(1, r) if r == rank(2)));
+        // Themselves
+        let err = error(game.execute(Action::Ask("a".into(), 0, rank(1))));
+        assert!(matches!(err, ActionError::InvalidQuestion(0, _)));
+        // Nobody
+        let err = error(game.execute(Action::Ask("a".into(), 7, rank(1))));
+        assert!(matches!(err, ActionError::InvalidQuestion(7, _)));
+        // A rank that is not in the deck
+        let err = error(game.execute(Action::Ask("a".into(), 1, rank(6))));
+        assert!(matches!(err, ActionError::InvalidQuestion(1, _)));
+        // Nothing changed
+        assert_eq!(game.state, GameState::Asking(0));
+        assert!(game.history.is_empty());
+    }
+
+    #[test]
+    fn asking_takes_every_card_of_the_rank() {
+        let mut game = setup(GameConfig::default(), &[&[1, 2], &[1, 1, 3]], &[4]);
+        let events = game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert!(matches!(events[..], [TurnEvent::Took(2)]));
+        assert_eq!(game.players[0].cards.len(), 4);
+        assert_eq!(game.players[1].cards, cards(&[1, 1, 3])[2..].to_vec());
+        // The player keeps asking
+        assert_eq!(game.state, GameState::Asking(0));
+        assert_eq!(game.players[0].asks, 1);
+    }
+
+    #[test]
+    fn taking_the_last_cards_of_a_rank_makes_a_group() {
+        let mut game = setup(GameConfig::default(), &[&[1, 1, 2], &[1, 1, 3]], &[4]);
+        let events = game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert!(matches!(events[..], [TurnEvent::Took(2), TurnEvent::Group(r)] if r == rank(1)));
+        assert_eq!(game.players[0].score, 1);
+        assert_eq!(game.players[0].cards.len(), 1);
+    }
+
+    #[test]
+    fn going_fishing_ends_the_turn() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3, 4]);
+        let events = game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert!(matches!(events[..], [TurnEvent::Took(0)]));
+        assert_eq!(game.state, GameState::Drawing(0));
+        let events = game.execute(Action::Draw("a".into(), rank(1))).unwrap();
+        assert!(matches!(events[..], [TurnEvent::Drawn(card)] if card.rank == rank(4)));
+        assert_eq!(game.state, GameState::Asking(1));
+        assert_eq!(game.players[0].fishes, 1);
+    }
+
+    #[test]
+    fn fishing_the_asked_rank_lets_the_player_ask_again() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3, 1]);
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        game.execute(Action::Draw("a".into(), rank(1))).unwrap();
+        assert_eq!(game.state, GameState::Asking(0));
+
+        let config = GameConfig {
+            fish_again: false,
+            ..GameConfig::default()
+        };
+        let mut game = setup(config, &[&[1], &[2]], &[3, 1]);
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        game.execute(Action::Draw("a".into(), rank(1))).unwrap();
+        assert_eq!(game.state, GameState::Asking(1));
+    }
+
+    #[test]
+    fn fishing_in_an_empty_deck_ends_the_turn() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[]);
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        let events = game.execute(Action::Draw("a".into(), rank(1))).unwrap();
+        assert!(matches!(events[..], [TurnEvent::DeckEmpty]));
+        assert_eq!(game.state, GameState::Asking(1));
+    }
+
+    #[test]
+    fn empty_hands_are_refilled_with_the_standard_rules() {
+        let mut game = setup(GameConfig::default(), &[&[1, 1, 1], &[1, 2]], &[3]);
+        let events = game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert!(matches!(
+            events[..],
+            [
+                TurnEvent::Took(1),
+                TurnEvent::Group(_),
+                TurnEvent::Refilled(0)
+            ]
+        ));
+        assert_eq!(game.players[0].cards, cards(&[3]));
+        assert_eq!(game.state, GameState::Asking(0));
+    }
+
+    #[test]
+    fn empty_handed_players_sit_out_when_the_deck_is_empty() {
+        let mut game = setup(GameConfig::default(), &[&[1, 1, 1], &[1, 2]], &[]);
+        let events = game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert!(matches!(
+            events[..],
+            [
+                TurnEvent::Took(1),
+                TurnEvent::Group(_),
+                TurnEvent::SatOut(0)
+            ]
+        ));
+        assert_eq!(game.state, GameState::Asking(1));
+    }
+
+    #[test]
+    fn sudden_death_ends_when_someone_runs_out_of_cards() {
+        let mut game = setup(sudden_death(), &[&[1, 1, 1, 2], &[1]], &[3]);
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        assert_eq!(
+            game.state,
+            GameState::GameOver(GameResults {
+                winners: vec!["A".into()],
+                winner_ids: vec!["a".into()],
+                score: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn standard_games_end_once_every_group_is_made() {
+        let mut game = setup(GameConfig::default(), &[&[1, 1, 1], &[1]], &[]);
+        game.players[1].score = 1;
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        // Both made a group, so both win
+        assert_eq!(
+            game.state,
+            GameState::GameOver(GameResults {
+                winners: vec!["A".into(), "B".into()],
+                winner_ids: vec!["a".into(), "b".into()],
+                score: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn leaving_puts_the_cards_back_and_keeps_the_turn() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2, 2], &[3]], &[4]);
+        game.state = GameState::Asking(2);
+        game.execute(Action::Leave("b".into())).unwrap();
+        assert_eq!(game.players.len(), 2);
+        assert_eq!(game.deck.len(), 3);
+        // Still the turn of c, who moved down a place
+        assert_eq!(game.state, GameState::Asking(1));
+        assert_eq!(game.players[1].id, "c");
+    }
+
+    #[test]
+    fn leaving_passes_the_turn_on() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2], &[3]], &[4]);
+        game.state = GameState::Drawing(2);
+        game.execute(Action::Leave("c".into())).unwrap();
+        assert_eq!(game.state, GameState::Asking(0));
+    }
+
+    #[test]
+    fn leaving_keeps_the_draw_of_someone_else() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2], &[3]], &[4]);
+        game.state = GameState::Drawing(2);
+        game.execute(Action::Leave("a".into())).unwrap();
+        // c still has to draw, from a place down
+        assert_eq!(game.state, GameState::Drawing(1));
+        assert_eq!(game.players[1].id, "c");
+        let err = error(game.execute(Action::Ask("c".into(), 0, rank(3))));
+        assert!(matches!(err, ActionError::CannotAsk(id) if id == "c"));
+        game.execute(Action::Draw("c".into(), rank(3))).unwrap();
+        assert_eq!(game.state, GameState::Asking(0));
+    }
+
+    #[test]
+    fn players_who_leave_a_started_game_are_kept() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2, 2], &[3]], &[4]);
+        game.players[1].score = 2;
+        game.execute(Action::Leave("b".into())).unwrap();
+        assert_eq!(game.departed.len(), 1);
+        assert_eq!(game.departed[0].id, "b");
+        assert!(game.departed[0].cards.is_empty());
+        // Not a winner, even with the best score
+        game.execute(Action::Leave("a".into())).unwrap();
+        assert!(matches!(game.state, GameState::GameOver(ref r) if r.winners == ["C"]));
+        assert_eq!(game.departed.len(), 2);
+    }
+
+    #[test]
+    fn nobody_leaves_a_finished_game() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        game.execute(Action::Close(CloseReason::Inactive)).unwrap();
+        let err = error(game.execute(Action::Leave("a".into())));
+        assert!(matches!(err, ActionError::GameIsOver));
+        assert_eq!(game.players.len(), 2);
+    }
+
+    #[test]
+    fn leaving_with_one_player_left_ends_the_game() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        game.execute(Action::Leave("a".into())).unwrap();
+        assert!(matches!(game.state, GameState::GameOver(ref r) if r.winners == ["B"]));
+    }
+
+    #[test]
+    fn leaving_the_lobby_doesnt_start_anything() {
+        let mut game = Game::new(GameConfig::default());
+        join(&mut game, "a").unwrap();
+        game.execute(Action::Leave("a".into())).unwrap();
+        assert!(game.players.is_empty());
+        assert!(game.departed.is_empty());
+        assert_eq!(game.state, GameState::Waiting);
+    }
+
+    #[test]
+    fn closed_lobbies_have_no_winners() {
+        let mut game = Game::new(GameConfig::default());
+        join(&mut game, "a").unwrap();
+        game.execute(Action::Close(CloseReason::LobbyExpired))
+            .unwrap();
+        assert!(matches!(game.state, GameState::GameOver(ref r) if r.winners.is_empty()));
+        assert_eq!(
+            game.history.last().unwrap().action,
+            Action::Close(CloseReason::LobbyExpired)
+        );
+    }
+
+    #[test]
+    fn nobody_wins_without_groups() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        game.execute(Action::Close(CloseReason::Inactive)).unwrap();
+        assert!(
+            matches!(game.state, GameState::GameOver(ref r) if r.winners.is_empty() && r.score == 0)
+        );
+    }
+
+    #[test]
+    fn closed_games_are_won_by_the_leaders() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        game.players[1].score = 2;
+        game.execute(Action::Close(CloseReason::Inactive)).unwrap();
+        assert!(matches!(game.state, GameState::GameOver(ref r) if r.winners == ["B"]));
+    }
+
+    #[test]
+    fn players_are_found_by_index_username_or_name() {
+        let mut game = Game::new(GameConfig::default());
+        for (id, name) in [("1", "Alice"), ("2", "Alicia"), ("3", "Bob")].iter() {
+            let username = Some(format!("user{}", id));
+            let action = Action::Join(id.to_string(), name.to_string(), username, None);
+            game.execute(action).unwrap();
+        }
+        let find = |query: &str| game.find_player(&Target::Query(query.into()));
+        assert_eq!(find("2").unwrap(), 2);
+        assert_eq!(find("@User3").unwrap(), 2);
+        assert_eq!(find("alice").unwrap(), 0);
+        assert_eq!(find("bo").unwrap(), 2);
+        assert_eq!(find("lici").unwrap(), 1);
+        assert_eq!(game.find_player(&Target::Id("2".into())).unwrap(), 1);
+
+        let err = find("ali").unwrap_err().downcast::<ActionError>().unwrap();
+        assert!(
+            matches!(err, ActionError::AmbiguousPlayer(_, names) if names == ["Alice", "Alicia"])
+        );
+        for query in ["carol", "@nobody", "", "7"].iter() {
+            let err = find(query).unwrap_err().downcast::<ActionError>().unwrap();
+            assert!(matches!(err, ActionError::UnknownPlayer(_)), "{}", query);
+        }
+        let err = game.find_player(&Target::Id("9".into())).unwrap_err();
+        assert!(matches!(
+            err.downcast::<ActionError>().unwrap(),
+            ActionError::UnknownPlayer(_)
+        ));
+    }
+
+    #[test]
+    fn games_with_the_same_seed_are_dealt_the_same() {
+        let deal = |seed| {
+            let mut game = Game::new(GameConfig {
+                seed: Some(seed),
+                ..GameConfig::default()
+            });
+            join(&mut game, "a").unwrap();
+            join(&mut game, "b").unwrap();
+            game.execute(Action::Start).unwrap();
+            game.players
+        };
+        let (first, second) = (deal(7), deal(7));
+        for (a, b) in first.iter().zip(second.iter()) {
+            assert_eq!((&a.id, &a.cards), (&b.id, &b.cards));
+        }
+    }
+
+    #[test]
+    fn executed_actions_are_recorded() {
+        let mut game = setup(GameConfig::default(), &[&[1], &[2]], &[3]);
+        game.execute(Action::Ask("a".into(), 1, rank(1))).unwrap();
+        let _ = game.execute(Action::Ask("a".into(), 1, rank(1)));
+        assert_eq!(game.history.len(), 1);
+        assert_eq!(game.history[0].action, Action::Ask("a".into(), 1, rank(1)));
+        assert_eq!(game.history[0].events, vec![TurnEvent::Took(0)]);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::entities::{
+    config::GameConfig,
+    game::{Action, Game, TurnEvent},
+    player::Player,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// An action that was executed on the game and what came out of it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Move {
+    pub action: Action,
+    pub events: Vec<TurnEvent>,
+}
+
+/// Everything needed to play a game again, move by move.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameExport {
+    pub config: GameConfig,
+    // Unknown for the games saved before it was stored, they cannot be replayed
+    pub seed: Option<u64>,
+    pub moves: Vec<Move>,
+}
+
+impl GameExport {
+    /// Rebuilds the game as it was after the first `moves` moves.
+    pub fn replay(&self, moves: usize) -> Result<Game> {
+        let mut config = self.config.clone();
+        config.seed = Some(
+            self.seed
+                .ok_or_else(|| anyhow!("The seed of the game is unknown"))?,
+        );
+        let mut game = Game::new(config);
+        for (number, recorded) in self.moves.iter().take(moves).enumerate() {
+            replay_move(&mut game, number, recorded)?;
+        }
+        Ok(game)
+    }
+
+    /// Replays the whole game, calling `f` with the players as they were before
+    /// each of the moves, the move and the game after it.
+    pub fn replay_with<F: FnMut(&[Player], &Move, &Game)>(&self, mut f: F) -> Result<Game> {
+        let mut game = self.replay(0)?;
+        for (number, recorded) in self.moves.iter().enumerate() {
+            let before = game.players.clone();
+            replay_move(&mut game, number, recorded)?;
+            f(&before, recorded, &game);
+        }
+        Ok(game)
+    }
+}
+
+/// Executes the recorded move, failing if the engine doesn't come up with the recorded events.
+fn replay_move(game: &mut Game, number: usize, recorded: &Move) -> Result<()> {
+    let events = game.execute(recorded.action.clone())?;
+    if events != recorded.events {
+        return Err(anyhow!("The replay diverged at move {}", number + 1));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::game::GameState;
+
+    fn played() -> Game {
+        let mut game = Game::new(GameConfig {
+            seed: Some(3),
+            ..GameConfig::default()
+        });
+        for id in ["a", "b"].iter() {
+            game.execute(Action::Join(id.to_string(), id.to_string(), None, None))
+                .unwrap();
+        }
+        game.execute(Action::Start).unwrap();
+        for _ in 0..10 {
+            let action = match game.state {
+                GameState::Asking(i) => {
+                    let player = &game.players[i];
+                    Action::Ask(player.id.clone(), 1 - i, player.ranks()[0])
+                }
+                GameState::Drawing(i) => {
+                    Action::Draw(game.players[i].id.clone(), game.players[i].ranks()[0])
+                }
+                _ => break,
+            };
+            game.execute(action).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn replays_rebuild_the_game() {
+        let game = played();
+        let export = game.export();
+        let replayed = export.replay(export.moves.len()).unwrap();
+        assert_eq!(replayed.state, game.state);
+        assert_eq!(replayed.history, game.history);
+        for (a, b) in replayed.players.iter().zip(game.players.iter()) {
+            assert_eq!((&a.id, &a.cards, a.score), (&b.id, &b.cards, b.score));
+        }
+    }
+
+    #[test]
+    fn replays_can_stop_halfway() {
+        let export = played().export();
+        let game = export.replay(3).unwrap();
+        assert_eq!(game.history, export.moves[..3].to_vec());
+        let mut moves = 0;
+        export.replay_with(|_, _, _| moves += 1).unwrap();
+        assert_eq!(moves, export.moves.len());
+    }
+
+    #[test]
+    fn tampered_replays_diverge() {
+        let mut export = played().export();
+        export.moves[2].events.push(TurnEvent::DeckEmpty);
+        let err = export.replay(export.moves.len()).unwrap_err();
+        assert_eq!(err.to_string(), "The replay diverged at move 3");
+        export.seed = export.seed.map(|seed| seed + 1);
+        assert!(export.replay(export.moves.len()).is_err());
+    }
+
+    #[test]
+    fn games_without_a_seed_cannot_be_replayed() {
+        let snapshot = serde_json::to_string(&played()).unwrap();
+        let mut snapshot: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+        // Snapshots saved before the seed was stored
+        let fields = snapshot.as_object_mut().unwrap();
+        fields.remove("seed");
+        fields.remove("rng");
+        let game: Game = serde_json::from_value(snapshot).unwrap();
+        assert_eq!(game.seed, None);
+        let err = game.export().replay(0).unwrap_err();
+        assert_eq!(err.to_string(), "The seed of the game is unknown");
+    }
+}
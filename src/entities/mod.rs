@@ -1,3 +1,6 @@
+pub mod card;
+pub mod config;
 pub mod deck;
 pub mod game;
+pub mod history;
 pub mod player;
@@ -1,59 +1,188 @@
-use serde::Serialize;
+use super::card::{Card, Rank};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Difficulty {
+    Easy,
+    Hard,
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "hard" => Ok(Difficulty::Hard),
+            other => Err(format!("Unknown difficulty '{}', use easy or hard", other)),
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Hard => write!(f, "hard"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Player {
     pub id: String,
     pub name: String,
-    pub cards: Vec<u8>,
+    // Telegram username, without the @
+    #[serde(default)]
+    pub username: Option<String>,
+    pub cards: Vec<Card>,
     pub score: u8,
+    // Computer controlled players keep the difficulty they were added with
+    #[serde(default)]
+    pub bot: Option<Difficulty>,
+    // Questions asked and times gone fishing, kept for the statistics
+    #[serde(default)]
+    pub asks: u16,
+    #[serde(default)]
+    pub fishes: u16,
 }
 
 impl Player {
-    pub fn remove_cards(&mut self, card: u8) -> Vec<u8> {
-        let removed: Vec<u8> = self
-            .cards
-            .iter()
-            .filter(|&&c| c == card)
-            .map(|&e| e)
-            .collect();
-        self.cards = self
+    pub fn is_bot(&self) -> bool {
+        self.bot.is_some()
+    }
+
+    /// Takes every card of the rank out of the hand.
+    pub fn remove_cards(&mut self, rank: Rank) -> Vec<Card> {
+        let removed: Vec<Card> = self
             .cards
             .iter()
-            .filter(|&&c| c != card)
-            .map(|&e| e)
+            .filter(|c| c.rank == rank)
+            .copied()
             .collect();
+        self.cards.retain(|c| c.rank != rank);
         removed
     }
 
+    pub fn has_rank(&self, rank: Rank) -> bool {
+        self.cards.iter().any(|c| c.rank == rank)
+    }
+
+    /// Distinct ranks in the hand, from the lowest.
+    pub fn ranks(&self) -> Vec<Rank> {
+        let mut ranks: Vec<Rank> = self.cards.iter().map(|c| c.rank).collect();
+        ranks.sort_unstable();
+        ranks.dedup();
+        ranks
+    }
+
     pub fn has_cards(&self) -> bool {
-        self.cards.len() > 0
+        !self.cards.is_empty()
     }
 
-    pub fn add_cards(&mut self, cards: &[u8]) {
+    pub fn add_cards(&mut self, cards: &[Card]) {
         self.cards.extend(cards);
     }
 
-    pub fn reduce_groups(&mut self) -> Vec<u8> {
-        let mut counter: [u8; 12] = [0; 12];
-        for &card in &self.cards {
-            counter[card as usize - 1] += 1;
+    /// Takes every group of `size` cards of the same rank out of the hand, returning their ranks.
+    pub fn reduce_groups(&mut self, size: u8) -> Vec<Rank> {
+        if size == 0 {
+            return vec![];
         }
-        self.cards = self
-            .cards
-            .clone()
-            .into_iter()
-            .filter(|&c| counter[c as usize - 1] < 4)
-            .collect();
-        let groups: Vec<u8> = counter
-            .iter()
-            .enumerate()
-            .fold(vec![], |mut acc, (index, curr)| {
-                if *curr >= 4 {
-                    acc.push((index as u8) + 1);
+        let mut counter: BTreeMap<Rank, u8> = BTreeMap::new();
+        for card in &self.cards {
+            *counter.entry(card.rank).or_default() += 1;
+        }
+        let mut groups = vec![];
+        for (rank, count) in counter {
+            for _ in 0..count / size {
+                groups.push(rank);
+            }
+            let grouped = (count / size * size) as usize;
+            let mut removed = 0;
+            self.cards.retain(|c| {
+                if c.rank == rank && removed < grouped {
+                    removed += 1;
+                    return false;
                 }
-                acc
+                true
             });
-        self.score += groups.len() as u8;
+        }
+        self.score = self.score.saturating_add(groups.len() as u8);
         groups
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::card::Suit;
+
+    fn player(ranks: &[u8]) -> Player {
+        let cards = ranks
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Card {
+                rank: Rank::new(value).unwrap(),
+                suit: Suit::ALL[i % 4],
+            })
+            .collect();
+        Player {
+            id: "1".into(),
+            name: "Player".into(),
+            username: None,
+            cards,
+            score: 0,
+            bot: None,
+            asks: 0,
+            fishes: 0,
+        }
+    }
+
+    fn values(ranks: Vec<Rank>) -> Vec<u8> {
+        ranks.iter().map(Rank::value).collect()
+    }
+
+    #[test]
+    fn groups_of_four_are_taken_out() {
+        let mut player = player(&[3, 1, 3, 3, 2, 3, 1]);
+        assert_eq!(values(player.reduce_groups(4)), vec![3]);
+        assert_eq!(player.score, 1);
+        assert_eq!(values(player.ranks()), vec![1, 2]);
+        assert_eq!(player.cards.len(), 3);
+    }
+
+    #[test]
+    fn every_group_is_taken_out_at_once() {
+        let mut player = player(&[5, 5, 5, 2, 2, 5, 7]);
+        assert_eq!(values(player.reduce_groups(2)), vec![2, 5, 5]);
+        assert_eq!(player.score, 3);
+        assert_eq!(values(player.ranks()), vec![7]);
+    }
+
+    #[test]
+    fn incomplete_groups_are_kept() {
+        let mut player = player(&[1, 1, 1, 2]);
+        assert!(player.reduce_groups(4).is_empty());
+        assert_eq!(player.cards.len(), 4);
+        assert_eq!(player.score, 0);
+    }
+
+    #[test]
+    fn empty_groups_are_never_made() {
+        let mut player = player(&[1, 1]);
+        assert!(player.reduce_groups(0).is_empty());
+        assert_eq!(player.cards.len(), 2);
+    }
+
+    #[test]
+    fn removing_a_rank_takes_every_card_of_it() {
+        let mut player = player(&[4, 9, 4]);
+        let removed = player.remove_cards(Rank::new(4).unwrap());
+        assert_eq!(removed.len(), 2);
+        assert!(!player.has_rank(Rank::new(4).unwrap()));
+        assert!(player.remove_cards(Rank::new(4).unwrap()).is_empty());
+        assert!(player.has_cards());
+    }
+}
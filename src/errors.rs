@@ -1,17 +1,47 @@
+use crate::entities::card::Rank;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ActionError {
-    #[error("Invalid question, check the option you chose or the card number.\n\nOption: {0}, Card: {1}\n\nRemember: You can't ask for a card that you don't have!🤥")]
-    InvalidQuestion(usize, u8),
+    #[error("Invalid question, check the option you chose or the card.\n\nOption: {0}, Card: {1}\n\nRemember: You can't ask for a card that you don't have!🤥")]
+    InvalidQuestion(usize, Rank),
     #[error("There is no player with id {0}")]
     InvalidPlayerId(String),
+    #[error("There is no player {0} in the game")]
+    UnknownPlayer(String),
+    #[error("{0} could be any of {}", .1.join(", "))]
+    AmbiguousPlayer(String, Vec<String>),
+    #[error("{0} is not a card")]
+    InvalidCard(String),
     #[error("{0} can't ask for cards now")]
     CannotAsk(String),
     #[error("{0} can't draw cards now")]
     CannotDraw(String),
     #[error("Game already started")]
     GameAlreadyStarted,
+    #[error("At least two players are needed to start the game")]
+    NotEnoughPlayers,
     #[error("{0} is already in the game")]
     PlayerAlreadyJoined(String),
+    #[error("The game is over")]
+    GameIsOver,
+}
+
+impl ActionError {
+    /// Name of the variant, to count the errors of each kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionError::InvalidQuestion(..) => "invalid_question",
+            ActionError::InvalidPlayerId(_) => "invalid_player_id",
+            ActionError::UnknownPlayer(_) => "unknown_player",
+            ActionError::AmbiguousPlayer(..) => "ambiguous_player",
+            ActionError::InvalidCard(_) => "invalid_card",
+            ActionError::CannotAsk(_) => "cannot_ask",
+            ActionError::CannotDraw(_) => "cannot_draw",
+            ActionError::GameAlreadyStarted => "game_already_started",
+            ActionError::NotEnoughPlayers => "not_enough_players",
+            ActionError::PlayerAlreadyJoined(_) => "player_already_joined",
+            ActionError::GameIsOver => "game_is_over",
+        }
+    }
 }
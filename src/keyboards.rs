@@ -0,0 +1,122 @@
+use crate::entities::card::Rank;
+use std::{fmt, str::FromStr};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Data attached to the inline keyboard buttons.
+///
+/// Every button carries the id of the chat where the game is played, so
+/// the keyboards sent privately to the players can be routed to the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallbackData {
+    // ChooseOpponent(chat_id, to)
+    ChooseOpponent(i64, usize),
+    // ChooseCard(chat_id, to, rank)
+    ChooseCard(i64, usize, Rank),
+}
+
+impl CallbackData {
+    pub fn chat_id(&self) -> i64 {
+        match self {
+            CallbackData::ChooseOpponent(chat_id, _) => *chat_id,
+            CallbackData::ChooseCard(chat_id, _, _) => *chat_id,
+        }
+    }
+
+    /// Chat of the game the button pressed by the user in `pressed_in` is for, if it can be trusted.
+    ///
+    /// Anyone can send any data, so buttons only count in the chat they were sent to:
+    /// the group of the game, or the private chat of the user, whose id is the user's.
+    pub fn game_chat(&self, pressed_in: i64, user_id: i64) -> Option<i64> {
+        if pressed_in == self.chat_id() || pressed_in == user_id {
+            Some(self.chat_id())
+        } else {
+            None
+        }
+    }
+
+    /// Name of the button, to count them with the commands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallbackData::ChooseOpponent(..) => "choose_opponent",
+            CallbackData::ChooseCard(..) => "choose_card",
+        }
+    }
+}
+
+impl fmt::Display for CallbackData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackData::ChooseOpponent(chat_id, to) => write!(f, "to:{}:{}", chat_id, to),
+            CallbackData::ChooseCard(chat_id, to, rank) => {
+                write!(f, "card:{}:{}:{}", chat_id, to, rank.value())
+            }
+        }
+    }
+}
+
+impl FromStr for CallbackData {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid callback data: {}", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts.as_slice() {
+            ["to", chat_id, to] => Ok(CallbackData::ChooseOpponent(
+                chat_id.parse().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+            )),
+            ["card", chat_id, to, rank] => Ok(CallbackData::ChooseCard(
+                chat_id.parse().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+                rank.parse().ok().and_then(Rank::new).ok_or_else(invalid)?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// One button per opponent, given by their index and name.
+pub fn opponents(chat_id: i64, opponents: &[(usize, String)]) -> InlineKeyboardMarkup {
+    opponents
+        .iter()
+        .fold(InlineKeyboardMarkup::default(), |kb, (index, name)| {
+            kb.append_row(vec![InlineKeyboardButton::callback(
+                name.clone(),
+                CallbackData::ChooseOpponent(chat_id, *index).to_string(),
+            )])
+        })
+}
+
+/// One button per rank, up to four buttons per row.
+pub fn cards(chat_id: i64, to: usize, ranks: &[Rank]) -> InlineKeyboardMarkup {
+    let buttons: Vec<InlineKeyboardButton> = ranks
+        .iter()
+        .map(|&rank| {
+            InlineKeyboardButton::callback(
+                rank.to_string(),
+                CallbackData::ChooseCard(chat_id, to, rank).to_string(),
+            )
+        })
+        .collect();
+    buttons
+        .chunks(4)
+        .fold(InlineKeyboardMarkup::default(), |kb, row| {
+            kb.append_row(row.to_vec())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_only_count_where_they_were_sent() {
+        let data = CallbackData::ChooseOpponent(-100, 1);
+        // In the group of the game and in private
+        assert_eq!(data.game_chat(-100, 7), Some(-100));
+        assert_eq!(data.game_chat(7, 7), Some(-100));
+        // Forged to play the game of another chat
+        assert_eq!(data.game_chat(-200, 7), None);
+        assert_eq!(data.game_chat(8, 7), None);
+    }
+}
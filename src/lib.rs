@@ -0,0 +1,15 @@
+pub mod actors;
+pub mod ai;
+pub mod alias;
+pub mod command;
+pub mod config;
+pub mod entities;
+pub mod errors;
+pub mod keyboards;
+pub mod metrics;
+pub mod rating;
+pub mod session;
+pub mod status;
+pub mod storage;
+pub mod templates;
+pub mod webhook;
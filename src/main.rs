@@ -1,32 +1,52 @@
 #[macro_use]
 extern crate lazy_static;
 
-mod actors;
-mod alias;
-mod command;
-mod entities;
-mod errors;
-mod templates;
-mod webhook;
-
-use crate::actors::run_async_actor;
-use actors::game::{
-    actor::GameActor,
-    messages::{GameActorMsg, IsOver, Message},
-};
-use alias::Cx;
 use anyhow::Result;
-use command::Command;
-use dashmap::DashMap;
-use dotenv;
-use std::sync::Arc;
-use teloxide::{prelude::*, types::Me, utils::command::BotCommand};
-use templates::*;
-use tokio::sync::{mpsc::Sender, oneshot};
-use webhook::webhook;
+use gofish::actors::{
+    game::{
+        actor::GameActor,
+        messages::{Callback, GameActorMsg, Message},
+        registry::GameRegistry,
+    },
+    run_async_actor,
+};
+use gofish::alias::Cx;
+use gofish::command::Command;
+use gofish::config::{Config, Updates};
+use gofish::keyboards::CallbackData;
+use gofish::metrics;
+use gofish::session::{game::GameSession, messages::GameCommand};
+use gofish::status;
+use gofish::storage::{sqlite::SqliteStore, GameStore, StatsStore};
+use gofish::templates::*;
+use gofish::webhook::webhook;
+use std::{process, sync::Arc, time::Duration};
+use teloxide::{
+    dispatching::update_listeners,
+    net,
+    prelude::*,
+    types::{InputFile, Me},
+    utils::command::BotCommand,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+// Players shown by /leaderboard
+const LEADERBOARD_SIZE: u32 = 10;
+// Actors nobody sends anything to for this long are dropped, in case their game is never closed
+const ACTOR_IDLE_TIMEOUT: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 
 lazy_static! {
-    static ref SENDERS: Arc<DashMap<i64, Sender<GameActorMsg>>> = Arc::new(DashMap::new());
+    static ref CONFIG: Config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
+    static ref DATABASE: Arc<SqliteStore> =
+        Arc::new(SqliteStore::open(&CONFIG.database_path).expect("Cannot open the database"));
+    static ref STORE: Arc<dyn GameStore> = DATABASE.clone();
+    static ref STATS: Arc<dyn StatsStore> = DATABASE.clone();
 }
 
 #[tokio::main]
@@ -35,50 +55,204 @@ async fn main() {
 }
 
 async fn run() {
-    dotenv::dotenv().ok();
     teloxide::enable_logging!();
     log::info!("Starting bot...");
-    let bot = Bot::from_env().auto_send();
+    lazy_static::initialize(&CONFIG);
+    let bot = Bot::with_client(&CONFIG.token, net::client_from_env()).auto_send();
+    let registry = GameRegistry::new(STORE.clone(), ACTOR_IDLE_TIMEOUT);
+    restore_games(&bot, &registry);
+    if let Some(addr) = CONFIG.status_address {
+        match warp::serve(status::routes(registry.clone())).try_bind_ephemeral(addr) {
+            Ok((_, server)) => {
+                tokio::spawn(server);
+            }
+            Err(err) => log::error!("Cannot serve the status on {}: {}", addr, err),
+        }
+    }
+    let callback_registry = registry.clone();
     let Me { user: bot_user, .. } = bot.get_me().await.unwrap();
     let bot_name = bot_user.username.expect("Bots must have usernames");
-    log::info!("listening...");
+    log::info!("listening with {:?}...", CONFIG.updates.mode());
     let cloned_bot = bot.clone();
-    teloxide::commands_repl_with_listener(bot, bot_name, execute, webhook(cloned_bot).await).await
+    let mut dispatcher = Dispatcher::new(bot)
+        .messages_handler(move |rx: DispatcherHandlerRx<AutoSend<Bot>, _>| {
+            UnboundedReceiverStream::new(rx)
+                .commands::<Command, _>(bot_name)
+                .for_each_concurrent(None, move |(cx, command)| {
+                    let registry = registry.clone();
+                    async move {
+                        report(execute(cx, command, &registry).await);
+                    }
+                })
+        })
+        .callback_queries_handler(
+            move |rx: DispatcherHandlerRx<AutoSend<Bot>, CallbackQuery>| {
+                UnboundedReceiverStream::new(rx).for_each_concurrent(None, move |cx| {
+                    let registry = callback_registry.clone();
+                    async move {
+                        report(handle_callback(cx.requester, cx.update, &registry).await);
+                    }
+                })
+            },
+        )
+        .setup_ctrlc_handler();
+    let error_handler = LoggingErrorHandler::with_custom_text("An error from the update listener");
+    match &CONFIG.updates {
+        Updates::Webhook(config) => {
+            let listener = match webhook(cloned_bot, config).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    log::error!("Cannot listen for updates: {:#}", err);
+                    process::exit(1);
+                }
+            };
+            dispatcher
+                .dispatch_with_listener(listener, error_handler)
+                .await
+        }
+        // Deletes the webhook, Telegram doesn't answer getUpdates while there is one
+        Updates::Polling => {
+            let listener = update_listeners::polling_default(cloned_bot).await;
+            status::receiving_updates();
+            dispatcher
+                .dispatch_with_listener(listener, error_handler)
+                .await
+        }
+    }
+}
+
+/// Logs the error of a handler, counting it if a request to Telegram failed.
+fn report(result: Result<()>) {
+    if let Err(err) = result {
+        metrics::error(&err);
+        log::error!("{:?}", err);
+    }
 }
 
-async fn execute(cx: Cx, command: Command) -> Result<()> {
+/// Spawns an actor for every game that was in progress when the bot went down.
+fn restore_games(bot: &AutoSend<Bot>, registry: &GameRegistry) {
+    let games = match STORE.load_all() {
+        Ok(games) => games,
+        Err(err) => {
+            log::error!("Cannot load the stored games: {}", err);
+            return;
+        }
+    };
+    log::info!("Restoring {} games...", games.len());
+    for (chat_id, game) in games {
+        let session = GameSession::restore(chat_id, game, STORE.clone(), STATS.clone())
+            .with_expiry(CONFIG.expiry);
+        registry.create(chat_id, |game_over| {
+            run_async_actor(GameActor::new(bot.clone(), session, game_over))
+        });
+    }
+}
+
+async fn execute(cx: Cx, command: Command, registry: &GameRegistry) -> Result<()> {
     let chat_id = cx.chat_id();
+    metrics::command(command.name());
 
     match command {
         Command::Help => {
             cx.answer(Command::descriptions()).await?;
         }
-        Command::NewGame => {
-            SENDERS.entry(chat_id).or_insert_with(|| {
-                let addr: Sender<GameActorMsg> = run_async_actor(GameActor::new());
-                addr
+        Command::NewGame { options } => {
+            let config = match CONFIG.default_rules.clone().with_options(&options) {
+                Ok(config) => config,
+                Err(err) => {
+                    cx.answer(invalid_options(&err)).await?;
+                    return Ok(());
+                }
+            };
+            // Whoever chooses the seed knows how the deck is shuffled
+            if config.seed.is_some() && !is_admin(&cx).await? {
+                cx.answer(ONLY_ADMINS).await?;
+                return Ok(());
+            }
+            let rules = game_rules(&config);
+            let session = GameSession::new(chat_id, config, STORE.clone(), STATS.clone())
+                .with_expiry(CONFIG.expiry);
+            let created = registry.create(chat_id, |game_over| {
+                run_async_actor(GameActor::new(cx.requester.clone(), session, game_over))
             });
-            cx.answer(GAME_CREATED).await?;
+            if created {
+                cx.answer(format!("{}\n\n{}", GAME_CREATED, rules)).await?;
+            } else {
+                cx.answer(GAME_ALREADY_CREATED).await?;
+            }
         }
         Command::EndGame => {
-            if let Some(_) = SENDERS.remove(&chat_id) {
+            if registry.remove(chat_id).await {
                 cx.answer(GAME_FINISHED).await?;
             } else {
                 cx.answer(NO_GAME_IN_PROGRESS).await?;
             }
         }
-        _ => {
-            let sender = get_sender(chat_id);
-            if let Some(sender) = sender {
-                let _ = sender
-                    .send(GameActorMsg::Message(Message(cx, command.into())))
-                    .await;
-                let (tx, rx) = oneshot::channel();
-                let _ = sender.send(GameActorMsg::IsOver(IsOver(tx))).await;
-                let is_over = rx.await?;
-                if is_over {
-                    SENDERS.remove(&chat_id);
+        Command::Stats => {
+            // Statistics of the author of the replied message, or of the sender
+            let author = cx.update.reply_to_message().and_then(|msg| msg.from());
+            let user = match author.or_else(|| cx.update.from()) {
+                Some(user) => user,
+                // Channel posts and anonymous admins have no sender
+                None => {
+                    cx.answer(UNKNOWN_SENDER).await?;
+                    return Ok(());
+                }
+            };
+            let stats = STATS.player_stats(&user.id.to_string())?;
+            cx.answer(player_stats(&user.first_name, &stats)).await?;
+        }
+        Command::Leaderboard { scope } => {
+            let global = match scope.trim().to_lowercase().as_str() {
+                "" => false,
+                "global" => true,
+                _ => {
+                    cx.answer(INVALID_LEADERBOARD).await?;
+                    return Ok(());
                 }
+            };
+            let chat = if global { None } else { Some(chat_id) };
+            let ratings = STATS.leaderboard(chat, LEADERBOARD_SIZE)?;
+            cx.answer(leaderboard(&ratings, global)).await?;
+        }
+        // Finished games are no secret, anyone can export them
+        Command::Export if registry.get(chat_id).is_none() => match STATS.last_game(chat_id)? {
+            Some(history) => {
+                let file =
+                    InputFile::memory(format!("gofish-{}.json", chat_id), history.into_bytes());
+                cx.answer_document(file).await?;
+            }
+            None => {
+                cx.answer(NO_GAME_TO_EXPORT).await?;
+            }
+        },
+        Command::Kick { .. } | Command::Seed | Command::Export if !is_admin(&cx).await? => {
+            cx.answer(ONLY_ADMINS).await?;
+        }
+        _ => {
+            if let Some(sender) = registry.get(chat_id) {
+                let user = match cx.update.from() {
+                    Some(user) => user.clone(),
+                    None => {
+                        cx.answer(UNKNOWN_SENDER).await?;
+                        return Ok(());
+                    }
+                };
+                let reply_to = cx
+                    .update
+                    .reply_to_message()
+                    .and_then(|msg| msg.from())
+                    .map(|author| author.id.to_string());
+                let name = command.name();
+                let command = match GameCommand::from_command(command, reply_to) {
+                    Some(command) => command,
+                    None => {
+                        log::warn!("/{} is not a game command", name);
+                        return Ok(());
+                    }
+                };
+                let msg = GameActorMsg::Message(Message(user, command));
+                let _ = sender.send(msg).await;
             } else {
                 cx.answer(NO_GAME_CREATED).await?;
             }
@@ -87,10 +261,57 @@ async fn execute(cx: Cx, command: Command) -> Result<()> {
     Ok(())
 }
 
-fn get_sender(chat_id: i64) -> Option<Sender<GameActorMsg>> {
-    let entry = SENDERS.get(&chat_id);
-    match entry {
-        Some(entry) => Some(entry.clone()),
-        _ => None,
+async fn handle_callback(
+    bot: AutoSend<Bot>,
+    query: CallbackQuery,
+    registry: &GameRegistry,
+) -> Result<()> {
+    let data = match query.data.as_deref().map(str::parse::<CallbackData>) {
+        Some(Ok(data)) => data,
+        _ => {
+            log::warn!("Unknown callback query: {:?}", query.data);
+            bot.answer_callback_query(query.id).await?;
+            return Ok(());
+        }
+    };
+    metrics::command(data.name());
+    let pressed_in = query.message.as_ref().map(|message| message.chat.id);
+    let chat_id = match pressed_in.and_then(|chat_id| data.game_chat(chat_id, query.from.id)) {
+        Some(chat_id) => chat_id,
+        None => {
+            log::warn!(
+                "Ignoring a button of chat {} pressed by {} in {:?}",
+                data.chat_id(),
+                query.from.id,
+                pressed_in
+            );
+            bot.answer_callback_query(query.id).await?;
+            return Ok(());
+        }
+    };
+    match registry.get(chat_id) {
+        Some(sender) => {
+            let msg = GameActorMsg::Callback(Box::new(Callback(query, data)));
+            let _ = sender.send(msg).await;
+        }
+        None => {
+            bot.answer_callback_query(query.id)
+                .text(NO_GAME_CREATED)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the sender of the message can administrate the chat.
+async fn is_admin(cx: &Cx) -> Result<bool> {
+    let user = match cx.update.from() {
+        Some(user) => user,
+        None => return Ok(false),
+    };
+    if cx.update.chat.is_private() || CONFIG.admins.contains(&user.id) {
+        return Ok(true);
     }
+    let member = cx.requester.get_chat_member(cx.chat_id(), user.id).await?;
+    Ok(member.is_privileged())
 }
@@ -0,0 +1,121 @@
+use crate::actors::{self, game::registry::GameRegistry};
+use crate::errors::ActionError;
+use lazy_static::lazy_static;
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use teloxide::RequestError;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+    static ref ACTIVE_GAMES: IntGauge = register(IntGauge::new(
+        "gofish_active_games",
+        "Games being played or waiting for players"
+    ));
+    static ref GAMES_STARTED: IntCounter = register(IntCounter::new(
+        "gofish_games_started_total",
+        "Games started since the bot started"
+    ));
+    static ref GAMES_FINISHED: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gofish_games_finished_total",
+            "Games finished since the bot started, by how they ended"
+        ),
+        &["reason"]
+    ));
+    static ref MAILBOX_DEPTH: IntGauge = register(IntGauge::new(
+        "gofish_actor_mailbox_depth",
+        "Messages waiting to be handled by the game actors"
+    ));
+    static ref MAILBOX_DEPTH_MAX: IntGauge = register(IntGauge::new(
+        "gofish_actor_mailbox_depth_max",
+        "Messages waiting to be handled by the busiest game actor"
+    ));
+    static ref ACTOR_RESTARTS: IntCounter = register(IntCounter::new(
+        "gofish_actor_restarts_total",
+        "Times the game actors recovered from a panic"
+    ));
+    static ref COMMANDS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gofish_commands_total",
+            "Commands and buttons received, by command"
+        ),
+        &["command"]
+    ));
+    static ref ACTION_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gofish_action_errors_total",
+            "Moves refused by the games, by error"
+        ),
+        &["error"]
+    ));
+    static ref TELEGRAM_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "gofish_telegram_errors_total",
+            "Requests to the Telegram API that failed, by error"
+        ),
+        &["error"]
+    ));
+}
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Invalid metric");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+pub fn game_started() {
+    GAMES_STARTED.inc();
+}
+
+/// Counts a game that ended, `reason` telling how.
+pub fn game_finished(reason: &str) {
+    GAMES_FINISHED.with_label_values(&[reason]).inc();
+}
+
+pub fn command(name: &str) {
+    COMMANDS.with_label_values(&[name]).inc();
+}
+
+pub fn action_error(error: &ActionError) {
+    ACTION_ERRORS.with_label_values(&[error.name()]).inc();
+}
+
+pub fn telegram_error(error: &RequestError) {
+    let name = match error {
+        RequestError::ApiError { .. } => "api",
+        RequestError::MigrateToChatId(_) => "migrate_to_chat_id",
+        RequestError::RetryAfter(_) => "retry_after",
+        RequestError::NetworkError(_) => "network",
+        RequestError::InvalidJson(_) => "invalid_json",
+        RequestError::Io(_) => "io",
+    };
+    TELEGRAM_ERRORS.with_label_values(&[name]).inc();
+}
+
+/// Counts the error if it comes from a request to Telegram.
+pub fn error(error: &anyhow::Error) {
+    if let Some(error) = error.downcast_ref::<RequestError>() {
+        telegram_error(error);
+    }
+}
+
+/// The metrics in the Prometheus text format, reading the ones of the games from `registry`.
+pub fn render(registry: &GameRegistry) -> String {
+    ACTIVE_GAMES.set(registry.len() as i64);
+    let depths = registry.mailbox_depths();
+    MAILBOX_DEPTH.set(depths.iter().sum::<usize>() as i64);
+    MAILBOX_DEPTH_MAX.set(depths.into_iter().max().unwrap_or(0) as i64);
+    let restarts = actors::restarts();
+    ACTOR_RESTARTS.inc_by(restarts.saturating_sub(ACTOR_RESTARTS.get()));
+
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        log::error!("Cannot encode the metrics: {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
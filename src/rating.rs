@@ -0,0 +1,71 @@
+use std::cmp::Ordering;
+
+/// Rating of a player that has never been rated.
+pub const INITIAL_RATING: f64 = 1500.0;
+/// Most points a player can win or lose in a single game.
+const K_FACTOR: f64 = 32.0;
+
+/// Chance of a player rated `rating` beating a player rated `opponent`.
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+/// New Elo ratings of the players once a game ends with the given scores.
+///
+/// The game is rated as if every player had played a match against each of the
+/// others: the one with the higher score wins it and equal scores are a draw.
+pub fn update<T: Ord>(ratings: &[f64], scores: &[T]) -> Vec<f64> {
+    let players = ratings.len();
+    if players < 2 {
+        return ratings.to_vec();
+    }
+    let k = K_FACTOR / (players - 1) as f64;
+    (0..players)
+        .map(|i| {
+            let delta: f64 = (0..players)
+                .filter(|&j| j != i)
+                .map(|j| {
+                    let actual = match scores[i].cmp(&scores[j]) {
+                        Ordering::Greater => 1.0,
+                        Ordering::Equal => 0.5,
+                        Ordering::Less => 0.0,
+                    };
+                    actual - expected_score(ratings[i], ratings[j])
+                })
+                .sum();
+            ratings[i] + k * delta
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winners_take_points_from_losers() {
+        let ratings = update(&[INITIAL_RATING; 2], &[3, 1]);
+        assert_eq!(ratings, vec![1516.0, 1484.0]);
+    }
+
+    #[test]
+    fn ties_between_equals_change_nothing() {
+        assert_eq!(update(&[1500.0, 1500.0], &[2, 2]), vec![1500.0, 1500.0]);
+    }
+
+    #[test]
+    fn ratings_are_zero_sum() {
+        let before = [1500.0, 1620.0, 1410.0, 1555.0];
+        let after = update(&before, &[4, 4, 1, 4]);
+        let total = |ratings: &[f64]| ratings.iter().sum::<f64>();
+        assert!((total(&before) - total(&after)).abs() < 1e-9);
+        // The loser pays, the favourite gains the least
+        assert!(after[2] < before[2]);
+        assert!(after[1] - before[1] < after[0] - before[0]);
+    }
+
+    #[test]
+    fn lonely_players_are_not_rated() {
+        assert_eq!(update(&[1700.0], &[5]), vec![1700.0]);
+    }
+}
@@ -0,0 +1,948 @@
+use super::messages::{GameCommand, Identity, Keyboard, Outbound, Recipient, Response};
+use crate::ai::{random::random_move, strategy_for, Strategy};
+use crate::entities::card::Rank;
+use crate::entities::config::GameConfig;
+use crate::entities::game::{Action, CloseReason, Game, GameResults, GameState, Target, TurnEvent};
+use crate::entities::player::{Difficulty, Player};
+use crate::errors::ActionError;
+use crate::metrics;
+use crate::storage::{GameStore, StatsStore};
+use crate::templates::*;
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, mem, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+const BOT_NAMES: [&str; 6] = ["Nemo", "Dory", "Marlin", "Bubbles", "Gill", "Bruce"];
+// Turns in a row a player can miss before being removed from the game
+const MAX_MISSED_TURNS: u8 = 3;
+// Time the chat is warned before a game is closed
+const CLOSING_WARNING: Duration = Duration::from_secs(5 * 60);
+
+/// How long games can go on without anybody playing before they are closed, forever by default.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Expiry {
+    // Time to start the game once it is created
+    pub lobby: Option<Duration>,
+    // Time without any move once the game has started
+    pub idle: Option<Duration>,
+}
+
+/// Time left for the player who has to ask.
+struct TurnTimer {
+    player_id: String,
+    started: Instant,
+    warned: bool,
+}
+
+/// A game being played, independent of the platform it is played on.
+///
+/// Commands go in and the messages for the players come out, it is up to
+/// a transport to deliver them.
+pub struct GameSession {
+    chat_id: i64,
+    game: Game,
+    store: Arc<dyn GameStore>,
+    stats: Arc<dyn StatsStore>,
+    // Strategies of the computer controlled players by player id
+    strategies: HashMap<String, Box<dyn Strategy>>,
+    turn: Option<TurnTimer>,
+    // Turns in a row each player has let time out
+    misses: HashMap<String, u8>,
+    // Messages produced by the command being handled
+    outbox: Vec<Outbound>,
+    // A line describing each move so far, for /history
+    summaries: Vec<String>,
+    expiry: Expiry,
+    // Last time a player made a move
+    last_active: Instant,
+    closing_warned: bool,
+}
+
+/// Maps the errors raised while handling a command to the text the players see.
+fn error_message(root_err: &anyhow::Error) -> String {
+    let message = match root_err.downcast_ref::<ActionError>() {
+        Some(err) => match err {
+            ActionError::InvalidQuestion(_, _) => INVALID_QUESTION,
+            ActionError::InvalidPlayerId(_) => INVALID_PLAYER,
+            ActionError::UnknownPlayer(query) => return unknown_player(query),
+            ActionError::AmbiguousPlayer(query, names) => return ambiguous_player(query, names),
+            ActionError::InvalidCard(card) => return invalid_card(card),
+            ActionError::CannotAsk(_) => NOT_YOUR_TURN,
+            ActionError::CannotDraw(_) => ERROR_DRAWING,
+            ActionError::GameAlreadyStarted => GAME_ALREADY_STARTED,
+            ActionError::NotEnoughPlayers => NOT_ENOUGH_PLAYERS,
+            ActionError::PlayerAlreadyJoined(_) => ALREADY_JOINED,
+            ActionError::GameIsOver => GAME_FINISHED,
+        },
+        None => UNKNOWN_ERROR,
+    };
+    message.into()
+}
+
+/// Describes every move of the game by replaying it.
+///
+/// Games that cannot be replayed, e.g. without a seed, get their moves described
+/// without knowing who was in the game at the time.
+fn summarize(game: &Game) -> Vec<String> {
+    let show_drawn = game.config.show_drawn;
+    let mut summaries = vec![];
+    let replayed = game.export().replay_with(|before, played, after| {
+        summaries.push(move_summary(before, played, &after.players, show_drawn))
+    });
+    if let Err(err) = replayed {
+        log::warn!("Cannot replay the history of the game: {}", err);
+        return game
+            .history
+            .iter()
+            .map(|played| move_summary(&[], played, &[], show_drawn))
+            .collect();
+    }
+    summaries
+}
+
+impl GameSession {
+    pub fn new(
+        chat_id: i64,
+        config: GameConfig,
+        store: Arc<dyn GameStore>,
+        stats: Arc<dyn StatsStore>,
+    ) -> Self {
+        Self::restore(chat_id, Game::new(config), store, stats)
+    }
+
+    pub fn restore(
+        chat_id: i64,
+        game: Game,
+        store: Arc<dyn GameStore>,
+        stats: Arc<dyn StatsStore>,
+    ) -> Self {
+        let strategies = game
+            .players
+            .iter()
+            .filter_map(|p| {
+                p.bot
+                    .map(|difficulty| (p.id.clone(), strategy_for(difficulty)))
+            })
+            .collect();
+        let summaries = summarize(&game);
+        let mut session = Self {
+            chat_id,
+            game,
+            store,
+            stats,
+            strategies,
+            turn: None,
+            misses: HashMap::new(),
+            outbox: vec![],
+            summaries,
+            expiry: Expiry::default(),
+            last_active: Instant::now(),
+            closing_warned: false,
+        };
+        session.restart_timer();
+        session
+    }
+
+    /// Closes the game when nobody plays it for the given times.
+    pub fn with_expiry(mut self, expiry: Expiry) -> Self {
+        self.expiry = expiry;
+        self
+    }
+
+    pub fn chat_id(&self) -> i64 {
+        self.chat_id
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn is_over(&self) -> bool {
+        matches!(self.game.state, GameState::GameOver(_))
+    }
+
+    /// Goes back to the last snapshot of the game, forgetting whatever happened after it.
+    pub fn rollback(&mut self) -> Result<()> {
+        let game = self
+            .store
+            .load(self.chat_id)?
+            .ok_or_else(|| anyhow!("There is no snapshot of the game of chat {}", self.chat_id))?;
+        let expiry = self.expiry;
+        *self = Self::restore(self.chat_id, game, self.store.clone(), self.stats.clone())
+            .with_expiry(expiry);
+        Ok(())
+    }
+
+    /// Forgets the snapshot of the game, so it is not restored anymore.
+    pub fn discard(&self) -> Result<()> {
+        self.store.remove(self.chat_id)
+    }
+
+    /// Handles a command sent by a player, letting the bots play afterwards.
+    pub fn handle(&mut self, from: &Identity, command: GameCommand) -> Response {
+        let moves = self.game.history.len();
+        let result = match command {
+            GameCommand::Ask(to, card) => self.ask_to(&from.id, &to, &card),
+            GameCommand::Join => self.join(from),
+            GameCommand::AddBot(difficulty) => self.add_bot(difficulty),
+            GameCommand::Start => self.start(),
+            GameCommand::Status => self.status(),
+            GameCommand::Leave => self.leave(&from.id),
+            GameCommand::Kick(target) => self.kick(&target),
+            GameCommand::Seed => self.seed(&from.id),
+            GameCommand::History(moves) => self.history(moves),
+            GameCommand::Export => self.export(&from.id),
+            GameCommand::ChooseOpponent(to) => self.choose_opponent(&from.id, to),
+            GameCommand::ChooseCard(to, card) => self.ask(&from.id, to, card),
+        };
+        if result.is_ok() {
+            self.played(&from.id, moves);
+        }
+        let result = result.and_then(|()| self.play_bots());
+        self.respond(result)
+    }
+
+    /// Keeps the game open if the command made any move since the first `moves`,
+    /// and forgets the turns the sender missed if one of them was theirs.
+    ///
+    /// Commands that don't change the game, like /status, don't count.
+    fn played(&mut self, player_id: &str, moves: usize) {
+        let played = &self.game.history[moves..];
+        if played.is_empty() {
+            return;
+        }
+        self.last_active = Instant::now();
+        self.closing_warned = false;
+        let asked = played
+            .iter()
+            .any(|m| matches!(&m.action, Action::Ask(id, _, _) if id == player_id));
+        if asked {
+            self.misses.remove(player_id);
+        }
+    }
+
+    /// Instant at which the player who has to ask must be warned or played for,
+    /// or the chat warned about the game being closed or the game closed.
+    pub fn deadline(&self) -> Option<Instant> {
+        let turn = self.turn.as_ref().map(|timer| {
+            let timeout = self.turn_timeout();
+            // Players get a warning halfway through their turn
+            let elapsed = if timer.warned { timeout } else { timeout / 2 };
+            timer.started + elapsed
+        });
+        match (turn, self.closing_deadline()) {
+            (Some(turn), Some(closing)) => Some(turn.min(closing)),
+            (turn, closing) => turn.or(closing),
+        }
+    }
+
+    /// Warns the player who has to ask or, if they were already warned, plays for them.
+    ///
+    /// Games nobody plays are closed instead, after warning the chat.
+    pub fn on_deadline(&mut self) -> Response {
+        let closing = self.closing_deadline();
+        if let (Some((reason, timeout)), Some(closing)) = (self.expiry_timeout(), closing) {
+            if closing <= Instant::now() {
+                return self.on_closing(reason, timeout);
+            }
+        }
+        let timer = match self.turn.as_mut() {
+            Some(timer) => timer,
+            None => return Response::default(),
+        };
+        let player_id = timer.player_id.clone();
+        let name = match self.game.get_player_by_id(&player_id) {
+            Some(player) => player.name.clone(),
+            None => return Response::default(),
+        };
+        if !timer.warned {
+            timer.warned = true;
+            let left = self.turn_timeout() - self.turn_timeout() / 2;
+            self.send(turn_warning(&name, left.as_secs()));
+            return self.respond(Ok(()));
+        }
+        self.turn = None;
+        let result = self
+            .miss_turn(&player_id, &name)
+            .and_then(|()| self.play_bots());
+        self.respond(result)
+    }
+
+    /// Why the game would be closed and after how long without commands, if it can be.
+    fn expiry_timeout(&self) -> Option<(CloseReason, Duration)> {
+        match self.game.state {
+            GameState::Waiting => Some((CloseReason::LobbyExpired, self.expiry.lobby?)),
+            GameState::Asking(_) | GameState::Drawing(_) => {
+                Some((CloseReason::Inactive, self.expiry.idle?))
+            }
+            GameState::GameOver(_) => None,
+        }
+    }
+
+    /// Instant at which the chat must be warned about the game being closed, or the game closed.
+    fn closing_deadline(&self) -> Option<Instant> {
+        let (_, timeout) = self.expiry_timeout()?;
+        let warning = CLOSING_WARNING.min(timeout / 2);
+        let elapsed = if self.closing_warned {
+            timeout
+        } else {
+            timeout - warning
+        };
+        Some(self.last_active + elapsed)
+    }
+
+    /// Warns the chat that the game is going to be closed or, if it was already warned, closes it.
+    fn on_closing(&mut self, reason: CloseReason, timeout: Duration) -> Response {
+        if !self.closing_warned {
+            self.closing_warned = true;
+            let left = CLOSING_WARNING.min(timeout / 2);
+            // Round up so a few seconds are not announced as 0 minutes
+            let minutes = (left.as_secs_f64() / 60.0).ceil() as u64;
+            self.send(closing_warning(reason, minutes));
+            return self.respond(Ok(()));
+        }
+        log::info!("Closing the game of chat {}: {:?}", self.chat_id, reason);
+        let result = self.close(reason);
+        self.respond(result)
+    }
+
+    /// Closes the game before it ends, without recording its results.
+    fn close(&mut self, reason: CloseReason) -> Result<()> {
+        self.play(Action::Close(reason))?;
+        metrics::game_finished(match reason {
+            CloseReason::LobbyExpired => "lobby_expired",
+            CloseReason::Inactive => "inactive",
+        });
+        self.snapshot();
+        self.turn = None;
+        self.send(game_closed(reason));
+        Ok(())
+    }
+
+    fn respond(&mut self, result: Result<()>) -> Response {
+        if let Some(err) = result.as_ref().err().and_then(|err| err.downcast_ref()) {
+            metrics::action_error(err);
+        }
+        Response {
+            messages: mem::take(&mut self.outbox),
+            error: result.err().map(|err| error_message(&err)),
+        }
+    }
+
+    /// Executes the action on the game and snapshots the result into the store,
+    /// recording the results once the game is over.
+    fn execute(&mut self, action: Action) -> Result<Vec<TurnEvent>> {
+        let was_over = self.is_over();
+        let starting = matches!(action, Action::Start);
+        let events = self.play(action)?;
+        if starting {
+            metrics::game_started();
+        }
+        if !was_over && self.is_over() {
+            metrics::game_finished("played");
+            if let Err(err) = self.stats.record(self.chat_id, &self.game) {
+                log::error!("Cannot record the game of chat {}: {}", self.chat_id, err);
+            }
+        }
+        self.snapshot();
+        self.restart_timer();
+        Ok(events)
+    }
+
+    /// Executes the action on the game, describing the move for /history.
+    fn play(&mut self, action: Action) -> Result<Vec<TurnEvent>> {
+        let before = self.game.players.clone();
+        let events = self.game.execute(action)?;
+        if let Some(played) = self.game.history.last() {
+            let show_drawn = self.game.config.show_drawn;
+            let summary = move_summary(&before, played, &self.game.players, show_drawn);
+            self.summaries.push(summary);
+        }
+        Ok(events)
+    }
+
+    fn snapshot(&self) {
+        let saved = if self.is_over() {
+            self.store.remove(self.chat_id)
+        } else {
+            self.store.save(self.chat_id, &self.game)
+        };
+        if let Err(err) = saved {
+            log::error!("Cannot snapshot the game of chat {}: {}", self.chat_id, err);
+        }
+    }
+
+    fn turn_timeout(&self) -> Duration {
+        Duration::from_secs(self.game.config.turn_timeout.into())
+    }
+
+    /// Gives a fresh turn time to the human player who has to ask, if any.
+    fn restart_timer(&mut self) {
+        self.turn = match self.game.state {
+            GameState::Asking(index)
+                if self.game.config.turn_timeout > 0 && !self.game.players[index].is_bot() =>
+            {
+                Some(TurnTimer {
+                    player_id: self.game.players[index].id.clone(),
+                    started: Instant::now(),
+                    warned: false,
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// Plays for the player whose time ran out, removing them after too many misses.
+    fn miss_turn(&mut self, player_id: &str, name: &str) -> Result<()> {
+        let misses = self.misses.entry(player_id.into()).or_default();
+        *misses += 1;
+        if *misses >= MAX_MISSED_TURNS {
+            self.misses.remove(player_id);
+            return self.remove_player(player_id, removed_afk(name, MAX_MISSED_TURNS));
+        }
+        let index = self.current_asker(player_id)?;
+        let (to, card) = self
+            .game
+            .with_moves_rng(|game, rng| random_move(game, index, rng))
+            .ok_or_else(|| ActionError::CannotAsk(player_id.into()))?;
+        self.send(turn_timed_out(name, &self.game.players[to].name, card));
+        self.ask(player_id, to, card)
+    }
+
+    fn leave(&mut self, player_id: &str) -> Result<()> {
+        let player = self
+            .game
+            .get_player_by_id(player_id)
+            .ok_or_else(|| ActionError::InvalidPlayerId(player_id.into()))?;
+        let announcement = player_left(&player.name);
+        self.remove_player(player_id, announcement)
+    }
+
+    fn kick(&mut self, target: &Target) -> Result<()> {
+        let player = &self.game.players[self.game.find_player(target)?];
+        let (player_id, announcement) = (player.id.clone(), player_kicked(&player.name));
+        self.remove_player(&player_id, announcement)
+    }
+
+    /// Takes the player out of the game and lets everyone know with the announcement.
+    fn remove_player(&mut self, player_id: &str, announcement: String) -> Result<()> {
+        let events = self.execute(Action::Leave(player_id.into()))?;
+        self.strategies.remove(player_id);
+        self.misses.remove(player_id);
+        self.send(announcement);
+        let mut refilled = vec![];
+        for event in events {
+            let msg = match event {
+                TurnEvent::Refilled(index) => {
+                    refilled.push(self.game.players[index].clone());
+                    refilled_hand(&self.game.players[index].name)
+                }
+                TurnEvent::SatOut(index) => sat_out(&self.game.players[index].name),
+                _ => continue,
+            };
+            self.send(msg);
+        }
+        self.send_status_to_players(&refilled);
+        self.check_game_state();
+        Ok(())
+    }
+
+    fn send<T: Into<String>>(&mut self, text: T) {
+        self.outbox
+            .push(Outbound::Message(Recipient::Group, text.into(), None));
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.execute(Action::Start)?;
+        self.outbox.push(Outbound::Dice);
+        self.send(GAME_STARTED);
+        self.send_status_to_players(&self.game.players.clone());
+        self.check_game_state();
+        Ok(())
+    }
+
+    fn join(&mut self, player: &Identity) -> Result<()> {
+        self.execute(Action::Join(
+            player.id.clone(),
+            player.name.clone(),
+            player.username.clone(),
+            None,
+        ))?;
+        self.send(welcome(&player.name));
+        Ok(())
+    }
+
+    fn add_bot(&mut self, difficulty: Difficulty) -> Result<()> {
+        // Bots that left the game leave a gap that can be reused
+        let number = (0..)
+            .find(|n| {
+                self.game
+                    .get_player_by_id(&format!("bot{}", n + 1))
+                    .is_none()
+            })
+            .unwrap();
+        let id = format!("bot{}", number + 1);
+        let name = format!("{} 🤖", BOT_NAMES[number % BOT_NAMES.len()]);
+        self.execute(Action::Join(
+            id.clone(),
+            name.clone(),
+            None,
+            Some(difficulty),
+        ))?;
+        self.strategies.insert(id, strategy_for(difficulty));
+        self.send(bot_joined(&name, difficulty));
+        Ok(())
+    }
+
+    /// Sends the player who is asking the cards they can ask the chosen opponent for.
+    fn choose_opponent(&mut self, player_id: &str, to: usize) -> Result<()> {
+        let index = self.current_asker(player_id)?;
+        let target = match self.game.players.get(to) {
+            Some(target) if to != index => target,
+            _ => return Err(ActionError::UnknownPlayer(to.to_string()).into()),
+        };
+        let ranks = self.game.players[index].ranks();
+        self.outbox.push(Outbound::Message(
+            Recipient::Player(player_id.into()),
+            choose_card(&target.name),
+            Some(Keyboard::Ranks(to, ranks)),
+        ));
+        Ok(())
+    }
+
+    fn current_asker(&self, player_id: &str) -> Result<usize> {
+        match self.game.state {
+            GameState::Asking(index) if self.game.players[index].id == player_id => Ok(index),
+            _ => Err(ActionError::CannotAsk(player_id.into()).into()),
+        }
+    }
+
+    /// Plays the turns of the computer controlled players until a human has to play.
+    fn play_bots(&mut self) -> Result<()> {
+        while let GameState::Asking(index) = self.game.state {
+            let id = self.game.players[index].id.clone();
+            let strategy = match self.strategies.get_mut(&id) {
+                Some(strategy) => strategy,
+                None => break,
+            };
+            let choice = self
+                .game
+                .with_moves_rng(|game, rng| strategy.choose(game, index, rng));
+            let (to, card) = match choice {
+                Some(choice) => choice,
+                None => {
+                    log::warn!("Bot {} has nothing to ask for", id);
+                    break;
+                }
+            };
+            let players = &self.game.players;
+            let message = bot_asks(&players[index].name, &players[to].name, card);
+            self.send(message);
+            self.ask(&id, to, card)?;
+        }
+        Ok(())
+    }
+
+    /// Asks the target player for cards, as typed in the `/ask` command.
+    fn ask_to(&mut self, player_id: &str, target: &Target, card: &str) -> Result<()> {
+        let to = self.game.find_player(target)?;
+        let card = card
+            .parse::<Rank>()
+            .map_err(|_| ActionError::InvalidCard(card.into()))?;
+        self.ask(player_id, to, card)
+    }
+
+    fn ask(&mut self, player_id: &str, to: usize, card: Rank) -> Result<()> {
+        let events = self.execute(Action::Ask(player_id.into(), to, card))?;
+        let name = self.game.get_player_by_id(player_id).unwrap().name.clone();
+        let target = self.game.players[to].clone();
+        let mut refilled = vec![];
+        for event in events {
+            let msg = match event {
+                TurnEvent::Took(quantity) => {
+                    self.observe(|s| s.observe_ask(player_id, &target.id, card, quantity));
+                    if quantity == 0 {
+                        Some(no_cards(&target.name))
+                    } else {
+                        Some(had_n_cards(&target.name, quantity, card))
+                    }
+                }
+                TurnEvent::Group(card) => {
+                    self.observe(|s| s.observe_group(card));
+                    Some(made_group(&name, card, self.game.config.group_size))
+                }
+                TurnEvent::Refilled(index) => {
+                    refilled.push(index);
+                    Some(refilled_hand(&self.game.players[index].name))
+                }
+                TurnEvent::SatOut(index) => Some(sat_out(&self.game.players[index].name)),
+                _ => None,
+            };
+            if let Some(msg) = msg {
+                self.send(msg);
+            }
+        }
+        if let GameState::Drawing(_) = self.game.state {
+            refilled.extend(self.draw(player_id, card)?);
+        }
+        let mut players = vec![
+            self.game.players[to].clone(),
+            self.game.get_player_by_id(player_id).unwrap().clone(),
+        ];
+        players.extend(refilled.into_iter().map(|i| self.game.players[i].clone()));
+        self.send_status_to_players(&players);
+        self.check_game_state();
+        Ok(())
+    }
+
+    /// Draws a card for the player, returning the players that refilled their hand.
+    fn draw(&mut self, player_id: &str, card: Rank) -> Result<Vec<usize>> {
+        let events = self.execute(Action::Draw(player_id.into(), card))?;
+        let name = self.game.get_player_by_id(player_id).unwrap().name.clone();
+        let mut refilled = vec![];
+        for event in events {
+            let msg = match event {
+                TurnEvent::Drawn(drawn) if drawn.rank == card && self.game.config.fish_again => {
+                    Some(drawn_expected_card(&name, drawn))
+                }
+                TurnEvent::Drawn(drawn) if self.game.config.show_drawn => {
+                    Some(drawn_shown_card(&name, drawn))
+                }
+                TurnEvent::Drawn(_) => Some(drawn_card(&name)),
+                TurnEvent::DeckEmpty => Some(EMPTY_DECK.into()),
+                TurnEvent::Group(card) => {
+                    self.observe(|s| s.observe_group(card));
+                    Some(made_group(&name, card, self.game.config.group_size))
+                }
+                TurnEvent::Refilled(index) => {
+                    refilled.push(index);
+                    Some(refilled_hand(&self.game.players[index].name))
+                }
+                TurnEvent::SatOut(index) => Some(sat_out(&self.game.players[index].name)),
+                _ => None,
+            };
+            if let Some(msg) = msg {
+                self.send(msg);
+            }
+        }
+        Ok(refilled)
+    }
+
+    /// Lets every computer controlled player know about something that happened.
+    fn observe<F: Fn(&mut dyn Strategy)>(&mut self, f: F) {
+        for strategy in self.strategies.values_mut() {
+            f(strategy.as_mut());
+        }
+    }
+
+    /// Tells the seed of the game only to the admin who asked, it reveals every hand.
+    fn seed(&mut self, player_id: &str) -> Result<()> {
+        let message = match self.game.seed {
+            Some(seed) => game_seed(seed),
+            None => UNKNOWN_SEED.into(),
+        };
+        self.outbox.push(Outbound::Message(
+            Recipient::Player(player_id.into()),
+            message,
+            None,
+        ));
+        Ok(())
+    }
+
+    /// Sends the last moves, as they were seen at the time they were played.
+    fn history(&mut self, moves: usize) -> Result<()> {
+        let first = self.summaries.len().saturating_sub(moves);
+        let text = history(&self.summaries[first..], first + 1);
+        self.send(text);
+        Ok(())
+    }
+
+    /// Sends the whole game as JSON only to the admin who asked, it reveals every hand.
+    fn export(&mut self, player_id: &str) -> Result<()> {
+        self.outbox.push(Outbound::Document(
+            Recipient::Player(player_id.into()),
+            format!("gofish-{}.json", self.chat_id),
+            serde_json::to_string_pretty(&self.game.export())?,
+        ));
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<()> {
+        self.send(game_status(&self.game.players, self.game.deck.len()));
+        Ok(())
+    }
+
+    fn check_game_state(&mut self) {
+        match &self.game.state {
+            GameState::Asking(index) if !self.game.players[*index].is_bot() => {
+                let opponents = self
+                    .game
+                    .players
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| i != index)
+                    .map(|(i, p)| (i, p.name.clone()))
+                    .collect();
+                self.outbox.push(Outbound::Message(
+                    Recipient::Group,
+                    ask_for_cards(&self.game.players[*index].name, &self.game.players),
+                    Some(Keyboard::Opponents(opponents)),
+                ));
+            }
+            GameState::GameOver(GameResults { winners, score, .. }) => {
+                let message = game_over(winners, *score);
+                self.send(message);
+            }
+            _ => {}
+        }
+    }
+
+    fn send_status_to_players(&mut self, players: &[Player]) {
+        for player in players.iter().filter(|p| !p.is_bot()) {
+            self.outbox.push(Outbound::Message(
+                Recipient::Player(player.id.clone()),
+                player_status(player),
+                None,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteStore;
+    use tokio::time;
+
+    fn identity(name: &str) -> Identity {
+        Identity {
+            id: name.to_lowercase(),
+            name: name.into(),
+            username: Some(name.to_lowercase()),
+        }
+    }
+
+    fn session(players: &[&str]) -> GameSession {
+        let db = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let config = GameConfig {
+            seed: Some(1),
+            ..GameConfig::default()
+        };
+        let mut session = GameSession::new(1, config, db.clone(), db);
+        for name in players {
+            session.handle(&identity(name), GameCommand::Join);
+        }
+        session
+    }
+
+    fn asker(session: &GameSession) -> Identity {
+        match session.game().state {
+            GameState::Asking(i) => identity(&session.game().players[i].name),
+            _ => panic!("Nobody is asking"),
+        }
+    }
+
+    #[test]
+    fn every_action_error_has_a_message() {
+        let errors = vec![
+            ActionError::InvalidQuestion(1, Rank::ACE),
+            ActionError::InvalidPlayerId("1".into()),
+            ActionError::UnknownPlayer("bob".into()),
+            ActionError::AmbiguousPlayer("al".into(), vec!["Alice".into(), "Alan".into()]),
+            ActionError::InvalidCard("joker".into()),
+            ActionError::CannotAsk("1".into()),
+            ActionError::CannotDraw("1".into()),
+            ActionError::GameAlreadyStarted,
+            ActionError::NotEnoughPlayers,
+            ActionError::PlayerAlreadyJoined("Alice".into()),
+            ActionError::GameIsOver,
+        ];
+        for err in errors {
+            let message = error_message(&err.into());
+            assert_ne!(message, UNKNOWN_ERROR);
+        }
+        assert_eq!(error_message(&anyhow::anyhow!("oops")), UNKNOWN_ERROR);
+    }
+
+    #[test]
+    fn cards_that_dont_exist_cannot_be_asked_for() {
+        let mut session = session(&["Alice", "Bob"]);
+        session.handle(&identity("Alice"), GameCommand::Start);
+        let from = asker(&session);
+        let response = session.handle(
+            &from,
+            GameCommand::Ask(Target::Query("1".into()), "joker".into()),
+        );
+        assert_eq!(response.error, Some(invalid_card("joker")));
+        assert!(session
+            .game()
+            .history
+            .iter()
+            .all(|m| !matches!(m.action, Action::Ask(..))));
+    }
+
+    #[test]
+    fn errors_are_reported_to_the_sender() {
+        let mut session = session(&["Alice"]);
+        let response = session.handle(&identity("Alice"), GameCommand::Join);
+        assert_eq!(response.error.as_deref(), Some(ALREADY_JOINED));
+        let response = session.handle(&identity("Alice"), GameCommand::Start);
+        assert_eq!(response.error.as_deref(), Some(NOT_ENOUGH_PLAYERS));
+        session.handle(&identity("Bob"), GameCommand::Join);
+        session.handle(&identity("Bob"), GameCommand::Start);
+        let response = session.handle(&identity("Carol"), GameCommand::Join);
+        assert_eq!(response.error.as_deref(), Some(GAME_ALREADY_STARTED));
+        let waiting = match asker(&session).name.as_str() {
+            "Alice" => "Bob",
+            _ => "Alice",
+        };
+        let response = session.handle(&identity(waiting), GameCommand::ChooseOpponent(0));
+        assert_eq!(response.error.as_deref(), Some(NOT_YOUR_TURN));
+        let response = session.handle(&identity("Carol"), GameCommand::Leave);
+        assert_eq!(response.error.as_deref(), Some(INVALID_PLAYER));
+    }
+
+    async fn wait_for_deadline(session: &mut GameSession) -> Response {
+        let deadline = session.deadline().expect("No deadline");
+        time::sleep_until(deadline).await;
+        session.on_deadline()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lobbies_nobody_starts_are_closed() {
+        let mut session = session(&["Alice"]).with_expiry(Expiry {
+            lobby: Some(Duration::from_millis(100)),
+            idle: None,
+        });
+        let response = wait_for_deadline(&mut session).await;
+        assert!(!session.is_over());
+        assert!(matches!(
+            &response.messages[..],
+            [Outbound::Message(Recipient::Group, text, None)]
+                if *text == closing_warning(CloseReason::LobbyExpired, 1)
+        ));
+        let response = wait_for_deadline(&mut session).await;
+        assert!(session.is_over());
+        assert!(matches!(
+            &response.messages[..],
+            [Outbound::Message(Recipient::Group, text, None)]
+                if *text == game_closed(CloseReason::LobbyExpired)
+        ));
+        let closed = &session.game().history.last().unwrap().action;
+        assert_eq!(*closed, Action::Close(CloseReason::LobbyExpired));
+        assert_eq!(session.deadline(), None);
+        // Closed games are not part of the statistics
+        assert_eq!(session.stats.player_stats("alice").unwrap().games, 0);
+    }
+
+    /// Makes the move the asker would make if their time ran out.
+    fn play_turn(session: &mut GameSession) -> Response {
+        let from = asker(session);
+        let index = session.current_asker(&from.id).unwrap();
+        let (to, card) = session
+            .game
+            .with_moves_rng(|game, rng| random_move(game, index, rng))
+            .unwrap();
+        session.handle(&from, GameCommand::ChooseCard(to, card))
+    }
+
+    #[test]
+    fn the_seed_decides_the_moves_made_for_the_players() {
+        let play = || {
+            let mut session = session(&["Alice"]);
+            session.handle(&identity("Alice"), GameCommand::AddBot(Difficulty::Easy));
+            session.handle(&identity("Alice"), GameCommand::AddBot(Difficulty::Hard));
+            session.handle(&identity("Alice"), GameCommand::Start);
+            for _ in 0..20 {
+                if !matches!(session.game().state, GameState::Asking(_)) {
+                    break;
+                }
+                play_turn(&mut session);
+            }
+            session.game().history.clone()
+        };
+        let history = play();
+        assert!(history
+            .iter()
+            .any(|m| matches!(&m.action, Action::Ask(id, _, _) if id.starts_with("bot"))));
+        assert_eq!(history, play());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn playing_keeps_the_game_open() {
+        let idle = Duration::from_millis(100);
+        let mut session = session(&["Alice", "Bob"]).with_expiry(Expiry {
+            lobby: None,
+            idle: Some(idle),
+        });
+        assert_eq!(session.deadline(), None);
+        session.handle(&identity("Alice"), GameCommand::Start);
+        wait_for_deadline(&mut session).await;
+        assert!(session.closing_warned);
+        // Commands that don't change the game don't count as playing
+        session.handle(&identity("Carol"), GameCommand::Status);
+        session.handle(&identity("Bob"), GameCommand::History(5));
+        assert!(session.closing_warned);
+        assert_eq!(play_turn(&mut session).error, None);
+        assert!(!session.closing_warned);
+        assert_eq!(session.deadline(), Some(Instant::now() + idle / 2));
+        wait_for_deadline(&mut session).await;
+        wait_for_deadline(&mut session).await;
+        assert!(session.is_over());
+    }
+
+    #[test]
+    fn missed_turns_are_only_forgotten_when_the_player_moves() {
+        let mut session = session(&["Alice", "Bob"]);
+        session.handle(&identity("Alice"), GameCommand::Start);
+        let from = asker(&session);
+        let waiting = match from.name.as_str() {
+            "Alice" => identity("Bob"),
+            _ => identity("Alice"),
+        };
+        session.misses.insert(from.id.clone(), 2);
+        session.misses.insert(waiting.id.clone(), 2);
+        session.handle(&identity("Carol"), GameCommand::Status);
+        session.handle(&from, GameCommand::Status);
+        let response = session.handle(&waiting, GameCommand::ChooseCard(0, Rank::ACE));
+        assert_eq!(response.error.as_deref(), Some(NOT_YOUR_TURN));
+        assert_eq!(session.misses.get(&from.id), Some(&2));
+        assert_eq!(session.misses.get(&waiting.id), Some(&2));
+        play_turn(&mut session);
+        assert_eq!(session.misses.get(&from.id), None);
+        assert_eq!(session.misses.get(&waiting.id), Some(&2));
+    }
+
+    #[test]
+    fn history_is_described_as_moves_are_played() {
+        let mut session = session(&["Alice", "Bob"]);
+        session.handle(&identity("Alice"), GameCommand::Start);
+        assert_eq!(session.summaries, summarize(session.game()));
+
+        // Without a seed the moves are still described, without replaying them
+        let mut game = session.game().clone();
+        game.seed = None;
+        let db = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let mut restored = GameSession::restore(1, game, db.clone(), db);
+        assert_eq!(restored.summaries.len(), restored.game().history.len());
+        let response = restored.handle(&identity("Alice"), GameCommand::History(10));
+        assert!(response.error.is_none());
+        assert!(response.messages.iter().any(|message| matches!(
+            message,
+            Outbound::Message(Recipient::Group, text, None) if text.contains("Bob joined")
+        )));
+    }
+
+    #[test]
+    fn finished_games_are_recorded() {
+        let mut session = session(&["Alice", "Bob"]);
+        session.handle(&identity("Alice"), GameCommand::Start);
+        let response = session.handle(&identity("Alice"), GameCommand::Leave);
+        assert_eq!(response.error, None);
+        assert!(session.is_over());
+        assert_eq!(session.stats.player_stats("bob").unwrap().wins, 1);
+        // Leaving counts as a loss
+        let alice = session.stats.player_stats("alice").unwrap();
+        assert_eq!((alice.games, alice.wins), (1, 0));
+    }
+}
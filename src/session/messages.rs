@@ -0,0 +1,130 @@
+use crate::{
+    command::Command,
+    entities::{card::Rank, game::Target, player::Difficulty},
+};
+
+// Moves shown by /history when no number is given
+const HISTORY_MOVES: usize = 10;
+const MAX_HISTORY_MOVES: usize = 50;
+
+/// Who sent a command.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub id: String,
+    pub name: String,
+    // Username on the platform, without the @
+    pub username: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum GameCommand {
+    Start,
+    Join,
+    AddBot(Difficulty),
+    Status,
+    // Ask(to, card)
+    Ask(Target, String),
+    Leave,
+    Kick(Target),
+    Seed,
+    // History(moves to show)
+    History(usize),
+    Export,
+    // ChooseOpponent(to): first step of asking with the buttons
+    ChooseOpponent(usize),
+    // ChooseCard(to, rank): second step of asking with the buttons
+    ChooseCard(usize, Rank),
+}
+
+impl GameCommand {
+    /// Converts the command, `reply_to` being the author of the message the command replied to.
+    ///
+    /// Returns `None` for the commands that are not about the game being played.
+    pub fn from_command(cmd: Command, reply_to: Option<String>) -> Option<Self> {
+        let target = |query: String| match reply_to {
+            Some(id) if query.is_empty() => Target::Id(id),
+            _ => Target::Query(query),
+        };
+        let command = match cmd {
+            Command::Join => GameCommand::Join,
+            Command::AddBot { difficulty } => GameCommand::AddBot(difficulty),
+            Command::Start => GameCommand::Start,
+            Command::Ask { to, card } => GameCommand::Ask(target(to), card),
+            Command::Status => GameCommand::Status,
+            Command::Leave => GameCommand::Leave,
+            Command::Kick { player } => GameCommand::Kick(target(player)),
+            Command::Seed => GameCommand::Seed,
+            Command::History { moves } => GameCommand::History(
+                moves
+                    .trim()
+                    .parse()
+                    .unwrap_or(HISTORY_MOVES)
+                    .min(MAX_HISTORY_MOVES),
+            ),
+            Command::Export => GameCommand::Export,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Recipient {
+    // Everyone playing, e.g. the group chat
+    Group,
+    // Player(id), privately
+    Player(String),
+}
+
+/// Choices offered to a player along with a message.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Keyboard {
+    // Opponents(index and name of every opponent)
+    Opponents(Vec<(usize, String)>),
+    // Ranks(to, ranks the player can ask for)
+    Ranks(usize, Vec<Rank>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outbound {
+    // Message(recipient, text, choices)
+    Message(Recipient, String, Option<Keyboard>),
+    // Document(recipient, file name, contents)
+    Document(Recipient, String, String),
+    // Roll a die in the group when the game starts
+    Dice,
+}
+
+/// Everything a command produced: the messages to deliver, in order,
+/// and the error that stopped it, if any, ready to be shown to the sender.
+#[derive(Default, Debug)]
+pub struct Response {
+    pub messages: Vec<Outbound>,
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_game_commands_are_converted() {
+        let history = Command::History {
+            moves: "500".into(),
+        };
+        assert!(matches!(
+            GameCommand::from_command(history, None),
+            Some(GameCommand::History(MAX_HISTORY_MOVES))
+        ));
+        let ask = Command::Ask {
+            to: "".into(),
+            card: "4".into(),
+        };
+        assert!(matches!(
+            GameCommand::from_command(ask, Some("1".into())),
+            Some(GameCommand::Ask(Target::Id(id), _)) if id == "1"
+        ));
+        assert!(GameCommand::from_command(Command::Help, None).is_none());
+        assert!(GameCommand::from_command(Command::EndGame, None).is_none());
+    }
+}
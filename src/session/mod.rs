@@ -0,0 +1,27 @@
+pub mod game;
+pub mod messages;
+pub mod telegram;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use messages::Outbound;
+
+/// Delivers the messages of a game session to the players.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn deliver(&self, message: &Outbound) -> Result<()>;
+
+    /// Delivers every message in order, returning the first error once all have been tried.
+    async fn deliver_all(&self, messages: &[Outbound]) -> Result<()> {
+        let mut result = Ok(());
+        for message in messages {
+            if let Err(err) = self.deliver(message).await {
+                log::warn!("Cannot deliver {:?}: {}", message, err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
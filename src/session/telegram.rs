@@ -0,0 +1,83 @@
+use super::{
+    messages::{Identity, Keyboard, Outbound, Recipient},
+    Transport,
+};
+use crate::{keyboards, metrics};
+use anyhow::Result;
+use async_trait::async_trait;
+use teloxide::{
+    prelude::*,
+    types::{InputFile, User},
+    RequestError,
+};
+
+/// Sends the messages of the game played in a Telegram chat.
+pub struct TelegramTransport {
+    bot: AutoSend<Bot>,
+    chat_id: i64,
+}
+
+impl TelegramTransport {
+    pub fn new(bot: AutoSend<Bot>, chat_id: i64) -> Self {
+        Self { bot, chat_id }
+    }
+
+    fn chat(&self, to: &Recipient) -> String {
+        match to {
+            Recipient::Group => self.chat_id.to_string(),
+            Recipient::Player(id) => id.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for TelegramTransport {
+    async fn deliver(&self, message: &Outbound) -> Result<()> {
+        let delivered = self.send(message).await;
+        if let Err(err) = &delivered {
+            metrics::telegram_error(err);
+        }
+        Ok(delivered?)
+    }
+}
+
+impl TelegramTransport {
+    async fn send(&self, message: &Outbound) -> Result<(), RequestError> {
+        match message {
+            Outbound::Message(to, text, keyboard) => {
+                let request = self.bot.send_message(self.chat(to), text.clone());
+                match keyboard {
+                    Some(Keyboard::Opponents(opponents)) => {
+                        request
+                            .reply_markup(keyboards::opponents(self.chat_id, opponents))
+                            .await?
+                    }
+                    Some(Keyboard::Ranks(to, ranks)) => {
+                        request
+                            .reply_markup(keyboards::cards(self.chat_id, *to, ranks))
+                            .await?
+                    }
+                    None => request.await?,
+                };
+            }
+            Outbound::Document(to, file_name, contents) => {
+                let file = InputFile::memory(file_name.clone(), contents.clone().into_bytes());
+                self.bot.send_document(self.chat(to), file).await?;
+            }
+            Outbound::Dice => {
+                self.bot.send_dice(self.chat_id).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&User> for Identity {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.to_string(),
+            name: user.first_name.clone(),
+            username: user.username.clone(),
+        }
+    }
+}
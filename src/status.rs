@@ -0,0 +1,100 @@
+use crate::actors::game::registry::GameRegistry;
+use crate::metrics;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
+
+// Content type of the Prometheus text format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Whether Telegram sends the updates, or the bot asks for them
+static RECEIVING_UPDATES: AtomicBool = AtomicBool::new(false);
+
+/// Tells the readiness probe that the webhook is registered or that polling started.
+pub fn receiving_updates() {
+    RECEIVING_UPDATES.store(true, Ordering::Relaxed);
+}
+
+fn ready(registry: &GameRegistry) -> bool {
+    RECEIVING_UPDATES.load(Ordering::Relaxed) && registry.is_alive()
+}
+
+/// `/healthz`, `/readyz` and `/metrics` for the platform running the bot.
+///
+/// The bot is ready once it gets updates and while its registry still drops finished games.
+pub fn routes(
+    registry: Arc<GameRegistry>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::path("healthz").and(warp::path::end()).map(|| "ok");
+    let readyz = {
+        let registry = registry.clone();
+        warp::path("readyz").and(warp::path::end()).map(move || {
+            if ready(&registry) {
+                reply::with_status("ready", StatusCode::OK)
+            } else {
+                reply::with_status("not ready", StatusCode::SERVICE_UNAVAILABLE)
+            }
+        })
+    };
+    let metrics = warp::path("metrics").and(warp::path::end()).map(move || {
+        reply::with_header(
+            metrics::render(&registry),
+            "content-type",
+            METRICS_CONTENT_TYPE,
+        )
+    });
+    warp::get().and(healthz.or(readyz).or(metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::ActionError, storage::sqlite::SqliteStore};
+    use std::time::Duration;
+
+    async fn get(registry: &Arc<GameRegistry>, path: &str) -> (StatusCode, String) {
+        let response = warp::test::request()
+            .path(path)
+            .reply(&routes(registry.clone()))
+            .await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn the_bot_is_ready_once_it_receives_updates() {
+        let store = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let registry = GameRegistry::new(store, Duration::from_secs(60));
+        assert_eq!(get(&registry, "/healthz").await.0, StatusCode::OK);
+        assert_eq!(
+            get(&registry, "/readyz").await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        receiving_updates();
+        assert_eq!(get(&registry, "/readyz").await.0, StatusCode::OK);
+        assert_eq!(get(&registry, "/status").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn metrics_are_exposed() {
+        let store = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let registry = GameRegistry::new(store, Duration::from_secs(60));
+        metrics::command("join");
+        metrics::action_error(&ActionError::NotEnoughPlayers);
+        let (status, body) = get(&registry, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        for metric in [
+            "gofish_active_games 0",
+            "gofish_actor_mailbox_depth 0",
+            "gofish_actor_restarts_total",
+            "gofish_commands_total{command=\"join\"}",
+            "gofish_action_errors_total{error=\"not_enough_players\"}",
+        ]
+        .iter()
+        {
+            assert!(body.contains(metric), "{} in {}", metric, body);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+pub mod sqlite;
+
+use crate::entities::game::Game;
+use anyhow::Result;
+
+/// Somewhere to keep in-progress games so they survive a restart.
+pub trait GameStore: Send + Sync {
+    /// Saves (or replaces) the snapshot of the game played in `chat_id`.
+    fn save(&self, chat_id: i64, game: &Game) -> Result<()>;
+    /// Forgets the snapshot of the game played in `chat_id`, if any.
+    fn remove(&self, chat_id: i64) -> Result<()>;
+    /// Loads the snapshot of the game played in `chat_id`, if any.
+    fn load(&self, chat_id: i64) -> Result<Option<Game>>;
+    /// Loads every stored game along with the chat it belongs to.
+    fn load_all(&self) -> Result<Vec<(i64, Game)>>;
+}
+
+/// Record of finished games, used to build the statistics of the players.
+pub trait StatsStore: Send + Sync {
+    /// Records the result of a finished game played in `chat_id`.
+    fn record(&self, chat_id: i64, game: &Game) -> Result<()>;
+    /// Statistics of the player with the given Telegram user id.
+    fn player_stats(&self, player_id: &str) -> Result<PlayerStats>;
+    /// Export of the last game finished in `chat_id`, as JSON.
+    fn last_game(&self, chat_id: i64) -> Result<Option<String>>;
+    /// Best rated players of the chat, or of every chat when `chat_id` is `None`.
+    fn leaderboard(&self, chat_id: Option<i64>, limit: u32) -> Result<Vec<Rating>>;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rating {
+    pub name: String,
+    pub rating: f64,
+    pub games: u32,
+}
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct PlayerStats {
+    pub games: u32,
+    pub wins: u32,
+    pub books: u32,
+    // Most games won in a row
+    pub best_streak: u32,
+}
+
+impl PlayerStats {
+    /// Percentage of games won.
+    pub fn win_rate(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        f64::from(self.wins) * 100.0 / f64::from(self.games)
+    }
+
+    pub fn average_books(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        f64::from(self.books) / f64::from(self.games)
+    }
+}
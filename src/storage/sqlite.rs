@@ -0,0 +1,386 @@
+use super::{GameStore, PlayerStats, Rating, StatsStore};
+use crate::entities::{
+    card::Suit,
+    game::{Game, GameState},
+};
+use crate::rating::{self, INITIAL_RATING};
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::{json, Value};
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+// Ratings scope shared by every chat
+const GLOBAL: i64 = 0;
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS games (
+                chat_id    INTEGER PRIMARY KEY,
+                snapshot   TEXT NOT NULL,
+                updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            );
+            CREATE TABLE IF NOT EXISTS results (
+                id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id     INTEGER NOT NULL,
+                started_at  INTEGER,
+                finished_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                history     TEXT
+            );
+            CREATE TABLE IF NOT EXISTS result_players (
+                result_id INTEGER NOT NULL REFERENCES results(id),
+                player_id TEXT NOT NULL,
+                name      TEXT NOT NULL,
+                bot       INTEGER NOT NULL,
+                books     INTEGER NOT NULL,
+                winner    INTEGER NOT NULL,
+                asks      INTEGER NOT NULL,
+                fishes    INTEGER NOT NULL,
+                PRIMARY KEY (result_id, player_id)
+            );
+            CREATE TABLE IF NOT EXISTS ratings (
+                scope     INTEGER NOT NULL,
+                player_id TEXT NOT NULL,
+                name      TEXT NOT NULL,
+                rating    REAL NOT NULL,
+                games     INTEGER NOT NULL,
+                PRIMARY KEY (scope, player_id)
+            );",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl GameStore for SqliteStore {
+    fn save(&self, chat_id: i64, game: &Game) -> Result<()> {
+        let snapshot = serde_json::to_string(game)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO games (chat_id, snapshot) VALUES (?1, ?2)
+             ON CONFLICT(chat_id) DO UPDATE SET
+                snapshot = excluded.snapshot,
+                updated_at = strftime('%s', 'now')",
+            params![chat_id, snapshot],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, chat_id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM games WHERE chat_id = ?1", params![chat_id])?;
+        Ok(())
+    }
+
+    fn load(&self, chat_id: i64) -> Result<Option<Game>> {
+        let conn = self.conn.lock().unwrap();
+        let snapshot: Option<String> = conn
+            .query_row(
+                "SELECT snapshot FROM games WHERE chat_id = ?1",
+                params![chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match snapshot {
+            Some(snapshot) => Ok(Some(read_snapshot(&snapshot)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_all(&self) -> Result<Vec<(i64, Game)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT chat_id, snapshot FROM games")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut games = vec![];
+        for row in rows {
+            let (chat_id, snapshot) = row?;
+            match read_snapshot(&snapshot) {
+                Ok(game) => games.push((chat_id, game)),
+                Err(err) => log::warn!("Skipping unreadable game of chat {}: {}", chat_id, err),
+            }
+        }
+        Ok(games)
+    }
+}
+
+impl StatsStore for SqliteStore {
+    fn record(&self, chat_id: i64, game: &Game) -> Result<()> {
+        let winners = match &game.state {
+            GameState::GameOver(results) => results.winner_ids.as_slice(),
+            _ => &[],
+        };
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let history = serde_json::to_string(&game.export())?;
+        tx.execute(
+            "INSERT INTO results (chat_id, started_at, history) VALUES (?1, ?2, ?3)",
+            params![chat_id, game.started_at.map(|secs| secs as i64), history],
+        )?;
+        let result_id = tx.last_insert_rowid();
+        // The departed are never among the winners
+        for player in game.players.iter().chain(&game.departed) {
+            tx.execute(
+                "INSERT INTO result_players
+                    (result_id, player_id, name, bot, books, winner, asks, fishes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    result_id,
+                    player.id,
+                    player.name,
+                    player.is_bot(),
+                    player.score,
+                    winners.contains(&player.id),
+                    player.asks,
+                    player.fishes
+                ],
+            )?;
+        }
+        update_ratings(&tx, chat_id, game)?;
+        update_ratings(&tx, GLOBAL, game)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn player_stats(&self, player_id: &str) -> Result<PlayerStats> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT books, winner FROM result_players
+             WHERE player_id = ?1 ORDER BY result_id",
+        )?;
+        let rows = stmt.query_map(params![player_id], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, bool>(1)?))
+        })?;
+        let mut stats = PlayerStats::default();
+        let mut streak = 0;
+        for row in rows {
+            let (books, winner) = row?;
+            stats.games += 1;
+            stats.books += books;
+            if winner {
+                stats.wins += 1;
+                streak += 1;
+                stats.best_streak = stats.best_streak.max(streak);
+            } else {
+                streak = 0;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn last_game(&self, chat_id: i64) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let history = conn
+            .query_row(
+                "SELECT history FROM results WHERE chat_id = ?1 ORDER BY id DESC LIMIT 1",
+                params![chat_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(history.flatten())
+    }
+
+    fn leaderboard(&self, chat_id: Option<i64>, limit: u32) -> Result<Vec<Rating>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT name, rating, games FROM ratings
+             WHERE scope = ?1 ORDER BY rating DESC, games DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![chat_id.unwrap_or(GLOBAL), limit], |row| {
+            Ok(Rating {
+                name: row.get(0)?,
+                rating: row.get(1)?,
+                games: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Reads the snapshot of a game, upgrading the ones saved before cards had a suit.
+fn read_snapshot(snapshot: &str) -> serde_json::Result<Game> {
+    let error = match serde_json::from_str(snapshot) {
+        Ok(game) => return Ok(game),
+        Err(err) => err,
+    };
+    let mut json: Value = serde_json::from_str(snapshot)?;
+    if !add_suits(&mut json) {
+        return Err(error);
+    }
+    serde_json::from_value(json)
+}
+
+/// Turns the cards stored as the number of their rank into cards of that rank,
+/// giving each copy of a rank its own suit. Returns whether there were any.
+fn add_suits(json: &mut Value) -> bool {
+    let mut copies: HashMap<u64, usize> = HashMap::new();
+    let mut upgraded = false;
+    let mut upgrade = |cards: Option<&mut Value>| {
+        let cards = cards.and_then(Value::as_array_mut).into_iter().flatten();
+        for card in cards {
+            if let Some(rank) = card.as_u64() {
+                let copy = copies.entry(rank).or_default();
+                *card = json!({ "rank": rank, "suit": Suit::ALL[*copy % Suit::ALL.len()] });
+                *copy += 1;
+                upgraded = true;
+            }
+        }
+    };
+    upgrade(json.get_mut("deck").and_then(|deck| deck.get_mut("cards")));
+    let players = json.get_mut("players").and_then(Value::as_array_mut);
+    for player in players.into_iter().flatten() {
+        upgrade(player.get_mut("cards"));
+    }
+    upgraded
+}
+
+/// Rates the human players of the finished game within the scope.
+fn update_ratings(tx: &Transaction, scope: i64, game: &Game) -> Result<()> {
+    // Whoever left comes last, whatever their groups
+    let players: Vec<_> = game
+        .players
+        .iter()
+        .map(|player| (player, true))
+        .chain(game.departed.iter().map(|player| (player, false)))
+        .filter(|(player, _)| !player.is_bot())
+        .collect();
+    if players.len() < 2 {
+        return Ok(());
+    }
+    let mut ratings = vec![];
+    for (player, _) in &players {
+        let rating: Option<f64> = tx
+            .query_row(
+                "SELECT rating FROM ratings WHERE scope = ?1 AND player_id = ?2",
+                params![scope, player.id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        ratings.push(rating.unwrap_or(INITIAL_RATING));
+    }
+    let scores: Vec<_> = players
+        .iter()
+        .map(|(player, stayed)| (*stayed, player.score))
+        .collect();
+    for ((player, _), rating) in players.iter().zip(rating::update(&ratings, &scores)) {
+        tx.execute(
+            "INSERT INTO ratings (scope, player_id, name, rating, games) VALUES (?1, ?2, ?3, ?4, 1)
+             ON CONFLICT(scope, player_id) DO UPDATE SET
+                name = excluded.name,
+                rating = excluded.rating,
+                games = games + 1",
+            params![scope, player.id, player.name, rating],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::{
+        config::GameConfig,
+        game::{Action, CloseReason},
+    };
+
+    #[test]
+    fn games_saved_before_cards_had_suits_are_upgraded() {
+        let snapshot = r#"{
+            "deck": {"cards": [2, 2, 3]},
+            "state": {"Asking": 0},
+            "players": [
+                {"id": "1", "name": "Alice", "cards": [1, 1, 2], "score": 1, "bot": null},
+                {"id": "2", "name": "Bob", "cards": [3, 3, 3], "score": 0, "bot": null}
+            ],
+            "config": {"variant": "Standard", "hand_size": null, "ranks": 12, "group_size": 4,
+                       "show_drawn": false, "fish_again": true, "turn_timeout": 120}
+        }"#;
+        let store = SqliteStore::open(":memory:").unwrap();
+        let conn = store.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO games (chat_id, snapshot) VALUES (1, ?1)",
+            params![snapshot],
+        )
+        .unwrap();
+        drop(conn);
+
+        let games = store.load_all().unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0].1;
+        assert_eq!(game.state, GameState::Asking(0));
+        assert_eq!(game.deck.len(), 3);
+        let cards = game.players.iter().map(|p| p.cards.len()).sum::<usize>();
+        assert_eq!(cards, 6);
+        // Every copy of a rank got a different suit
+        let twos: Vec<Suit> = game.players[0]
+            .cards
+            .iter()
+            .filter(|card| card.rank.value() == 2)
+            .map(|card| card.suit)
+            .collect();
+        assert_eq!(twos, vec![Suit::ALL[2]]);
+        let threes: Vec<Suit> = game.players[1].cards.iter().map(|card| card.suit).collect();
+        assert_eq!(threes, Suit::ALL[1..].to_vec());
+        assert!(store.load(1).unwrap().is_some());
+    }
+
+    fn finished(players: &[(&str, u8)]) -> Game {
+        let mut game = Game::new(GameConfig::default());
+        for (id, _) in players {
+            let join = Action::Join(id.to_string(), id.to_uppercase(), None, None);
+            game.execute(join).unwrap();
+        }
+        game.execute(Action::Start).unwrap();
+        for (player, &(_, score)) in game.players.iter_mut().zip(players) {
+            player.score = score;
+        }
+        game.execute(Action::Close(CloseReason::Inactive)).unwrap();
+        game
+    }
+
+    #[test]
+    fn the_winners_of_the_game_are_recorded() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store
+            .record(1, &finished(&[("a", 2), ("b", 2), ("c", 1)]))
+            .unwrap();
+        let wins = |id| store.player_stats(id).unwrap().wins;
+        assert_eq!((wins("a"), wins("b"), wins("c")), (1, 1, 0));
+    }
+
+    #[test]
+    fn nobody_wins_a_game_without_groups() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        store.record(1, &finished(&[("a", 0), ("b", 0)])).unwrap();
+        for id in ["a", "b"].iter() {
+            let stats = store.player_stats(id).unwrap();
+            assert_eq!((stats.games, stats.wins), (1, 0));
+        }
+    }
+
+    #[test]
+    fn leaving_is_rated_as_last_place() {
+        let mut game = finished(&[("a", 0), ("b", 0), ("c", 0)]);
+        // Someone left with the most groups
+        let mut leaver = game.players.remove(0);
+        leaver.score = 3;
+        let name = leaver.name.clone();
+        game.departed.push(leaver);
+        let store = SqliteStore::open(":memory:").unwrap();
+        store.record(1, &game).unwrap();
+        let ratings = store.leaderboard(Some(1), 10).unwrap();
+        assert_eq!(ratings.len(), 3);
+        assert_eq!(ratings[2].name, name);
+        assert!(ratings[2].rating < INITIAL_RATING);
+    }
+}
@@ -1,48 +1,198 @@
-use crate::entities::player::Player;
+use crate::entities::{
+    card::{Card, Rank},
+    config::{GameConfig, Variant},
+    game::{Action, CloseReason, TurnEvent},
+    history::Move,
+    player::{Difficulty, Player},
+};
+use crate::storage::{PlayerStats, Rating};
 
-pub const GAME_STARTED: &'static str = "Game started, GO FISH! 🧜‍♀️";
-pub const GAME_FINISHED: &'static str = "The game has finished!";
-pub const NO_GAME_IN_PROGRESS: &'static str = "There is no game in progress";
-pub const NO_GAME_CREATED: &'static str = "The game has not been created yet!";
-pub const INVALID_QUESTION: &'static str =
+pub const GAME_STARTED: &str = "Game started, GO FISH! 🧜‍♀️";
+pub const GAME_FINISHED: &str = "The game has finished!";
+pub const NO_GAME_IN_PROGRESS: &str = "There is no game in progress";
+pub const NO_GAME_CREATED: &str = "The game has not been created yet!";
+pub const INVALID_QUESTION: &str =
     "Invalid question! Check if the option and the card provided are correct";
-pub const INVALID_PLAYER: &'static str = "Sorry you can't ask you are not playing!";
-pub const GAME_CREATED: &'static str =
-    "Game created! Start joining and send start to start fishing";
-pub const NOT_YOUR_TURN: &'static str = "Hey is not your turn! You can't ask!";
-pub const ERROR_DRAWING: &'static str = "Error drawing :(";
-pub const GAME_ALREADY_STARTED: &'static str = "The game has already started!";
-pub const ALREADY_JOINED: &'static str = "You have already joined!";
-pub const EMPTY_DECK: &'static str = "The deck is empty!!!";
-pub const UNKNOWN_ERROR: &'static str = "An error sending a message occurred!\n\nMake sure that all game participants have started the bot on their private chats to receive your cards!!\n\nOtherwise, open an issue to: https://github.com/JasterV/gofish_bot";
+pub const INVALID_PLAYER: &str = "Sorry, you are not playing!";
+pub const ONLY_ADMINS: &str = "Only the chat admins can do that!";
+pub const GAME_CREATED: &str = "Game created! Start joining and send start to start fishing";
+pub const GAME_ALREADY_CREATED: &str =
+    "There is already a game in this chat! Join it or send endgame to finish it";
+pub const NOT_YOUR_TURN: &str = "Hey is not your turn! You can't ask!";
+pub const ERROR_DRAWING: &str = "Error drawing :(";
+pub const GAME_ALREADY_STARTED: &str = "The game has already started!";
+pub const NOT_ENOUGH_PLAYERS: &str = "At least two players are needed to start the game!";
+pub const ALREADY_JOINED: &str = "You have already joined!";
+pub const EMPTY_DECK: &str = "The deck is empty!!!";
+pub const INVALID_LEADERBOARD: &str = "Send /leaderboard for this chat or /leaderboard global";
+pub const UNKNOWN_SENDER: &str = "Sorry, I can't tell who you are! Send it from your own account";
+pub const UNKNOWN_SEED: &str =
+    "The seed of this game is unknown 🌱 It was started before seeds were stored";
+pub const NO_HISTORY: &str = "Nothing has happened yet!";
+pub const NO_GAME_TO_EXPORT: &str = "There is no game to export yet!";
+pub const GAME_RESTORED: &str =
+    "Oops, something went wrong! 🐙 The game goes on from the last move, try again";
+pub const GAME_CRASHED: &str =
+    "Oops, something went wrong and the game is lost 😢 Send /newgame to play another one";
+pub const UNKNOWN_ERROR: &str = "An error sending a message occurred!\n\nMake sure that all game participants have started the bot on their private chats to receive your cards!!\n\nOtherwise, open an issue to: https://github.com/JasterV/gofish_bot";
+
+pub fn invalid_options(err: &str) -> String {
+    format!("{}\n\nSend help to see the available options", err)
+}
+
+pub fn game_rules(config: &GameConfig) -> String {
+    let on_off = |value: bool| if value { "on" } else { "off" };
+    format!(
+        "RULES:\n\tVariant: {}\n\tHand size: {}\n\tCards: A to {}\n\tGroups of: {}\n\tShow drawn cards: {}\n\tFish again: {}",
+        match config.variant {
+            Variant::Standard => "standard",
+            Variant::SuddenDeath => "sudden death",
+        },
+        config
+            .hand_size
+            .map(|size| size.to_string())
+            .unwrap_or_else(|| "7 (5 with 4 or more players)".into()),
+        Rank::first(config.ranks).last().unwrap_or(Rank::ACE),
+        config.group_size,
+        on_off(config.show_drawn),
+        on_off(config.fish_again),
+    )
+}
 
 pub fn welcome(name: &str) -> String {
     format!("Hi {}, welcome to Go Fish!", name)
 }
 
+pub fn bot_joined(name: &str, difficulty: Difficulty) -> String {
+    format!("{} ({}) has joined the game, good luck!", name, difficulty)
+}
+
+pub fn bot_asks(name: &str, to: &str, card: Rank) -> String {
+    format!("{} asks {} for a {}", name, to, card)
+}
+
 pub fn no_cards(name: &str) -> String {
-    format!("{} had no cards with that number, lets draw!", name)
+    format!("{} had no cards of that rank, lets draw!", name)
 }
 
-pub fn had_n_cards(name: &str, quantity: u8, card: usize) -> String {
+pub fn had_n_cards(name: &str, quantity: u8, card: Rank) -> String {
     format!(
-        "{} had {} cards with the number {}, keep asking!",
+        "{} had {} cards of rank {}, keep asking!",
         name, quantity, card
     )
 }
 
-pub fn made_group(name: &str, card: u8) -> String {
-    format!("{} has made a group of four {}", name, card)
+pub fn made_group(name: &str, card: Rank, size: u8) -> String {
+    match size {
+        2 => format!("{} has made a pair of {}", name, card),
+        _ => format!("{} has made a group of four {}", name, card),
+    }
 }
 
 pub fn drawn_card(name: &str) -> String {
     format!("{} has drawn a card", name)
 }
 
-pub fn drawn_expected_card(name: &str, card: u8) -> String {
+pub fn drawn_shown_card(name: &str, card: Card) -> String {
+    format!("{} has drawn a {}", name, card)
+}
+
+pub fn drawn_expected_card(name: &str, card: Card) -> String {
     format!("{} has drawn a {}!! Keep asking!", name, card)
 }
 
+pub fn refilled_hand(name: &str) -> String {
+    format!("{} ran out of cards and has drawn one from the deck", name)
+}
+
+pub fn sat_out(name: &str) -> String {
+    format!(
+        "{} has no cards and the deck is empty, skipping their turn",
+        name
+    )
+}
+
+pub fn unknown_player(query: &str) -> String {
+    if query.is_empty() {
+        return "Who do you want to ask? Type their name or reply to one of their messages".into();
+    }
+    format!("There is no player '{}' in the game! 🤷", query)
+}
+
+pub fn ambiguous_player(query: &str, names: &[String]) -> String {
+    format!(
+        "'{}' could be any of {}, be more specific!",
+        query,
+        names.join(", ")
+    )
+}
+
+pub fn invalid_card(card: &str) -> String {
+    format!("'{}' is not a card! 🃏", card)
+}
+
+pub fn player_left(name: &str) -> String {
+    format!(
+        "{} has left the game, their cards go back to the deck 👋",
+        name
+    )
+}
+
+pub fn player_kicked(name: &str) -> String {
+    format!(
+        "{} has been kicked out of the game, their cards go back to the deck 🥾",
+        name
+    )
+}
+
+pub fn turn_warning(name: &str, seconds: u64) -> String {
+    format!(
+        "⏰ {}, hurry up! You have {} seconds left to ask",
+        name, seconds
+    )
+}
+
+pub fn closing_warning(reason: CloseReason, minutes: u64) -> String {
+    match reason {
+        CloseReason::LobbyExpired => format!(
+            "⏳ Nobody has started the game yet, it will be closed in {} minutes!",
+            minutes
+        ),
+        CloseReason::Inactive => format!(
+            "💤 Nobody has played for a while, the game will be closed in {} minutes unless someone plays!",
+            minutes
+        ),
+    }
+}
+
+pub fn close_reason(reason: CloseReason) -> &'static str {
+    match reason {
+        CloseReason::LobbyExpired => "nobody started it",
+        CloseReason::Inactive => "nobody played for too long",
+    }
+}
+
+pub fn game_closed(reason: CloseReason) -> String {
+    format!(
+        "The game was closed, {}. Send /newgame to play another one!",
+        close_reason(reason)
+    )
+}
+
+pub fn turn_timed_out(name: &str, to: &str, card: Rank) -> String {
+    format!(
+        "{} took too long 😴, asking {} for a {} for them",
+        name, to, card
+    )
+}
+
+pub fn removed_afk(name: &str, misses: u8) -> String {
+    format!(
+        "{} missed {} turns in a row and has been removed from the game, their cards go back to the deck",
+        name, misses
+    )
+}
+
 pub fn game_status(players: &[Player], cards: usize) -> String {
     format!(
         "GAME STATUS:\n\nPlayers info:\n\n{}\n\nDeck remaining cards: {}",
@@ -62,34 +212,158 @@ pub fn game_status(players: &[Player], cards: usize) -> String {
 
 pub fn ask_for_cards(from: &str, players: &[Player]) -> String {
     format!(
-        "{} lets ask someone for a card😇:\n\nType '/ask <option> <card> with one of the following options:\n\n{}'",
+        "{} lets ask someone for a card😇:\n\nTap one of the players below or type '/ask <player> <card>' with the name or the option of the player:\n\n{}",
         from,
         players.iter()
-        .enumerate()        
+        .enumerate()
         .map(|(index, player)| format!("{}) {}", index, player.name.clone()))
         .collect::<Vec<String>>()
         .join("\n")
     )
 }
 
+pub fn choose_card(to: &str) -> String {
+    format!("Which card do you want to ask {} for? 🤔", to)
+}
+
+pub fn card_chosen(to: &str, card: Rank) -> String {
+    format!("You asked {} for a {}", to, card)
+}
+
 pub fn player_status(player: &Player) -> String {
+    let mut cards = player.cards.clone();
+    cards.sort_unstable();
     format!(
         "Hi {0}! Here is your status 😃:\n\tCards: {1}\n\tscore: {2}",
         player.name.clone(),
-        player
-            .cards
+        cards
             .iter()
             .map(|e| e.to_string())
             .collect::<Vec<String>>()
-            .join(", "),
+            .join(" "),
         player.score
     )
 }
 
 pub fn game_over(winners: &[String], score: u8) -> String {
+    if winners.is_empty() {
+        return "Game Over!\n\tNobody made a group, so nobody won".into();
+    }
     format!(
         "Game Over!\n\tWinners 👑: {}\n\tScore: {}",
         winners.join(", "),
         score
     )
 }
+
+pub fn game_seed(seed: u64) -> String {
+    format!(
+        "The seed of the game is {0} 🌱\n\nCreate a game with /newgame seed={0} and play the same moves to replay it",
+        seed
+    )
+}
+
+pub fn player_stats(name: &str, stats: &PlayerStats) -> String {
+    if stats.games == 0 {
+        return format!("{} has not finished any game yet 🎣", name);
+    }
+    format!(
+        "STATS of {}📊:\n\tGames played: {}\n\tWins: {} ({:.0}%)\n\tAverage groups: {:.1}\n\tBest winning streak: {}",
+        name,
+        stats.games,
+        stats.wins,
+        stats.win_rate(),
+        stats.average_books(),
+        stats.best_streak
+    )
+}
+
+pub fn leaderboard(ratings: &[Rating], global: bool) -> String {
+    let title = if global {
+        "GLOBAL LEADERBOARD 🌍"
+    } else {
+        "LEADERBOARD 🏆"
+    };
+    if ratings.is_empty() {
+        return format!(
+            "{}\n\nNobody has been rated yet, finish a game with friends! 🎣",
+            title
+        );
+    }
+    format!(
+        "{}\n\n{}",
+        title,
+        ratings
+            .iter()
+            .enumerate()
+            .map(|(i, r)| format!(
+                "{}. {} - {:.0} ({} games)",
+                i + 1,
+                r.name,
+                r.rating,
+                r.games
+            ))
+            .collect::<Vec<String>>()
+            .join("\n")
+    )
+}
+
+/// One line describing a move, given the players before and after it.
+///
+/// Players that cannot be found are shown by their id, or as `?` when the move refers to their place.
+pub fn move_summary(
+    before: &[Player],
+    played: &Move,
+    after: &[Player],
+    show_drawn: bool,
+) -> String {
+    let name = |id: &str| {
+        before
+            .iter()
+            .find(|p| p.id == id)
+            .map_or_else(|| id.to_string(), |p| p.name.clone())
+    };
+    let index_name = |index: usize| after.get(index).map_or("?", |p| p.name.as_str());
+    let mut summary = match &played.action {
+        Action::Start => "The game started".to_string(),
+        Action::Join(_, player, _, _) => format!("{} joined", player),
+        Action::Ask(id, to, rank) => format!(
+            "{} asked {} for {}",
+            name(id),
+            before.get(*to).map_or("?", |p| p.name.as_str()),
+            rank
+        ),
+        Action::Draw(id, _) => format!("{} went fishing", name(id)),
+        Action::Leave(id) => format!("{} left", name(id)),
+        Action::Close(reason) => format!("The game was closed: {}", close_reason(*reason)),
+    };
+    for event in &played.events {
+        let note = match event {
+            TurnEvent::Took(0) => "got nothing".to_string(),
+            TurnEvent::Took(quantity) => format!("got {}", quantity),
+            TurnEvent::Drawn(card) if show_drawn => format!("drew {}", card),
+            TurnEvent::Group(rank) => format!("made a group of {}", rank),
+            TurnEvent::DeckEmpty => "the deck was empty".to_string(),
+            TurnEvent::Refilled(index) => format!("{} refilled their hand", index_name(*index)),
+            TurnEvent::SatOut(index) => format!("{} sat out", index_name(*index)),
+            _ => continue,
+        };
+        summary = format!("{}, {}", summary, note);
+    }
+    summary
+}
+
+pub fn history(moves: &[String], first: usize) -> String {
+    if moves.is_empty() {
+        return NO_HISTORY.into();
+    }
+    format!(
+        "HISTORY 📜:\n\n{}",
+        moves
+            .iter()
+            .enumerate()
+            .map(|(i, summary)| format!("{}. {}", first + i, summary))
+            .collect::<Vec<String>>()
+            .join("\n")
+    )
+}
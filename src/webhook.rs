@@ -1,71 +1,830 @@
-use reqwest::{StatusCode, Url};
-use std::{convert::Infallible, env, net::SocketAddr};
+use crate::config::{TlsConfig, WebhookConfig};
+use crate::status;
+use anyhow::{anyhow, Context, Result};
+use futures::FutureExt;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode, Url,
+};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+use std::{
+    convert::{Infallible, TryFrom},
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use teloxide::{
     dispatching::{
-        stop_token::AsyncStopToken,
+        stop_token::{AsyncStopFlag, AsyncStopToken},
         update_listeners::{self, StatefulListener},
     },
     prelude::*,
     types::Update,
 };
-use tokio::sync::mpsc;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedSender},
+    time::{self, Instant},
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier, ServerName},
+    },
+    TlsAcceptor, TlsConnector,
+};
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use warp::Filter;
+use warp::{
+    hyper::{
+        body::Bytes,
+        server::conn::Http,
+        service::{service_fn, Service},
+        Body, Request,
+    },
+    Filter,
+};
 
-async fn handle_rejection(error: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
-    log::error!("Cannot process the request due to: {:?}", error);
-    Ok(StatusCode::INTERNAL_SERVER_ERROR)
+// Header Telegram sends the secret given to setWebhook in
+const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+// Updates are a few kilobytes, anything bigger doesn't come from Telegram
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+// Length of the secret generated when none is configured
+const SECRET_LENGTH: usize = 32;
+// How often the files of the TLS certificate are checked for a renewed one
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Time a client has to make a TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Bytes the handshake made to check a certificate can have in flight
+const HANDSHAKE_BUFFER_SIZE: usize = 16 * 1024;
+// Pause after failing to accept a connection, so the failure isn't logged in a busy loop
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A single address or a network in CIDR notation, e.g. `149.154.160.0/20`.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
 }
 
-pub async fn webhook(bot: AutoSend<Bot>) -> impl update_listeners::UpdateListener<Infallible> {
-    // Heroku auto defines a port value
-    let teloxide_token = env::var("TELOXIDE_TOKEN").expect("TELOXIDE_TOKEN env variable missing");
-    let port: u16 = env::var("PORT")
-        .expect("PORT env variable missing")
-        .parse()
-        .expect("PORT value to be integer");
-    // Heroku host example .: "heroku-ping-pong-bot.herokuapp.com"
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
 
-    let host = env::var("HOST").expect("have HOST env variable");
-    let path = format!("bot{}", teloxide_token);
-    let url = Url::parse(&format!("https://{}/{}", host, path)).unwrap();
+impl TryFrom<String> for IpRange {
+    type Error = String;
 
-    bot.set_webhook(url).await.expect("Cannot setup a webhook");
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
 
-    let (tx, rx) = mpsc::unbounded_channel();
+impl FromStr for IpRange {
+    type Err = String;
 
-    let server = warp::post()
-        .and(warp::path(path))
-        .and(warp::body::json())
-        .map(move |json: serde_json::Value| {
-            if let Ok(update) = Update::try_parse(&json) {
-                tx.send(Ok(update))
-                    .expect("Cannot send an incoming update from the webhook")
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not an IP address or network", s);
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&p| p <= max)
+                .ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+/// What a request must have for its update to be trusted.
+#[derive(Clone)]
+pub struct WebhookSecurity {
+    // Secret Telegram sends back in every request
+    pub secret: String,
+    // Addresses requests may come from, any if empty
+    pub allowed_ips: Vec<IpRange>,
+    // Take the address of the sender from the X-Forwarded-For header added by a proxy
+    pub behind_proxy: bool,
+}
+
+impl WebhookSecurity {
+    /// Takes the settings of the webhook, making up a secret if none is set.
+    pub fn new(config: &WebhookConfig) -> Self {
+        let secret = config.secret.clone().unwrap_or_else(|| {
+            thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(SECRET_LENGTH)
+                .map(char::from)
+                .collect()
+        });
+        Self {
+            secret,
+            allowed_ips: config.allowed_ips.clone(),
+            behind_proxy: config.behind_proxy,
+        }
+    }
+
+    /// Why the request must be refused, if it must.
+    fn check(
+        &self,
+        remote: Option<SocketAddr>,
+        forwarded: Option<&str>,
+        secret: Option<&str>,
+    ) -> Option<StatusCode> {
+        if !self.allowed_ips.is_empty() {
+            // Proxies append the address they got the request from
+            let ip = match (self.behind_proxy, forwarded) {
+                (true, Some(forwarded)) => {
+                    forwarded.rsplit(',').next().unwrap().trim().parse().ok()
+                }
+                (true, None) => None,
+                (false, _) => remote.map(|addr| addr.ip()),
+            };
+            let allowed = ip.is_some_and(|ip| self.allowed_ips.iter().any(|r| r.contains(ip)));
+            if !allowed {
+                log::warn!("Refusing a webhook request from {:?}", ip);
+                return Some(StatusCode::FORBIDDEN);
             }
+        }
+        let secret = secret.unwrap_or_default().as_bytes();
+        if !constant_time_eq(secret, self.secret.as_bytes()) {
+            log::warn!("Refusing a webhook request without the right secret");
+            return Some(StatusCode::UNAUTHORIZED);
+        }
+        None
+    }
+}
+
+/// Compares the secrets without telling how much of them matched by the time it takes.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn handle_rejection(error: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    let status = if error.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if error.find::<warp::reject::PayloadTooLarge>().is_some() {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else if error.find::<warp::reject::LengthRequired>().is_some() {
+        StatusCode::LENGTH_REQUIRED
+    } else if error.find::<warp::reject::MethodNotAllowed>().is_some() {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    log::warn!("Cannot process the request due to: {:?}", error);
+    Ok(status)
+}
 
-            StatusCode::OK
+/// Reads the updates posted to `path`, sending the ones that can be trusted and parsed to `tx`.
+///
+/// Updates that cannot be parsed are logged and acknowledged anyway, so Telegram
+/// doesn't send them again and again.
+fn updates(
+    path: String,
+    security: Arc<WebhookSecurity>,
+    tx: UnboundedSender<Result<Update, Infallible>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Infallible> + Clone {
+    warp::post()
+        .and(warp::path(path))
+        .and(warp::path::end())
+        .and(warp::addr::remote())
+        .and(warp::ext::optional::<Peer>())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::header::optional::<String>(SECRET_HEADER))
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::bytes())
+        .map(
+            move |remote: Option<SocketAddr>,
+                  peer: Option<Peer>,
+                  forwarded: Option<String>,
+                  secret: Option<String>,
+                  body: Bytes| {
+                let remote = remote.or_else(|| peer.map(|Peer(addr)| addr));
+                if let Some(refused) =
+                    security.check(remote, forwarded.as_deref(), secret.as_deref())
+                {
+                    return refused;
+                }
+                let json: serde_json::Value = match serde_json::from_slice(&body) {
+                    Ok(json) => json,
+                    Err(err) => {
+                        log::warn!("Skipping an update that is not JSON: {}", err);
+                        return StatusCode::OK;
+                    }
+                };
+                match Update::try_parse(&json) {
+                    Ok(update) => {
+                        if tx.send(Ok(update)).is_err() {
+                            log::error!("Cannot send an incoming update from the webhook");
+                        }
+                    }
+                    Err(err) => log::warn!("Skipping an unknown update {}: {}", json, err),
+                }
+                StatusCode::OK
+            },
+        )
+        .recover(handle_rejection)
+}
+
+/// Points the bot to the webhook, asking Telegram to send the secret with every update.
+///
+/// Errors leave out the URL of the request, which holds the token of the bot.
+/// Uploads `certificate` for Telegram to trust it, when it is self-signed.
+async fn set_webhook(bot: &Bot, url: &Url, secret: &str, certificate: Option<&[u8]>) -> Result<()> {
+    let method = bot
+        .api_url()
+        .join(&format!("bot{}/setWebhook", bot.token()))?;
+    let mut form = Form::new()
+        .text("url", url.to_string())
+        .text("secret_token", secret.to_string());
+    if let Some(certificate) = certificate {
+        let file = Part::bytes(certificate.to_vec()).file_name("certificate.pem");
+        form = form.part("certificate", file);
+    }
+    let response: serde_json::Value = bot
+        .client()
+        .post(method)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)?
+        .json()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    if response["ok"] != true {
+        return Err(anyhow!(
+            "Cannot setup a webhook: {}",
+            response["description"]
+        ));
+    }
+    Ok(())
+}
+
+/// Contents of the certificate and key files.
+#[derive(Clone, PartialEq)]
+struct Certificate {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Certificate {
+    fn read(config: &TlsConfig) -> io::Result<Self> {
+        Ok(Certificate {
+            cert: fs::read(&config.cert)?,
+            key: fs::read(&config.key)?,
         })
-        .recover(handle_rejection);
+    }
+}
+
+/// A certificate along with the acceptor making TLS handshakes with it.
+struct Acceptor {
+    certificate: Certificate,
+    tls: TlsAcceptor,
+}
+
+impl Acceptor {
+    /// Takes the certificate, unless a handshake cannot be made with it.
+    async fn new(certificate: Certificate) -> Result<Self> {
+        let chain = rustls_pemfile::certs(&mut certificate.cert.as_slice())?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect();
+        let key = rustls_pemfile::read_all(&mut certificate.key.as_slice())?
+            .into_iter()
+            .find_map(|item| match item {
+                Item::RSAKey(key) | Item::PKCS8Key(key) => Some(rustls::PrivateKey(key)),
+                Item::X509Certificate(_) => None,
+            })
+            .ok_or_else(|| anyhow!("There is no private key"))?;
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let tls = TlsAcceptor::from(Arc::new(config));
+        // A key that doesn't match the certificate is only found out by the handshakes
+        if !handshake(&tls).await {
+            return Err(anyhow!(
+                "Cannot make a TLS handshake, does the key match the certificate?"
+            ));
+        }
+        Ok(Acceptor { certificate, tls })
+    }
+}
+
+/// Takes any certificate, only checking the server has its key.
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Connects over TLS trusting any certificate, to check the ones the webhook serves.
+fn connector() -> TlsConnector {
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
 
+/// Whether a TLS handshake with `acceptor` succeeds.
+async fn handshake(acceptor: &TlsAcceptor) -> bool {
+    let name = ServerName::try_from("localhost").expect("localhost is a valid name");
+    // Both ends of the handshake are made in memory, the side failing first hangs up on the other
+    let (client, server) = tokio::io::duplex(HANDSHAKE_BUFFER_SIZE);
+    let handshakes = async {
+        let (client, server) =
+            tokio::join!(connector().connect(name, client), acceptor.accept(server));
+        client.is_ok() && server.is_ok()
+    };
+    time::timeout(HANDSHAKE_TIMEOUT, handshakes)
+        .await
+        .unwrap_or(false)
+}
+
+/// Address of the sender of a request served over TLS, which warp cannot tell.
+#[derive(Clone, Copy)]
+struct Peer(SocketAddr);
+
+/// Serves `filter` to the client at `peer` over TLS.
+async fn serve_connection<F>(filter: F, acceptor: TlsAcceptor, stream: TcpStream, peer: SocketAddr)
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+{
+    let stream = match time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            log::debug!("Cannot make a TLS handshake with {}: {}", peer, err);
+            return;
+        }
+        Err(_) => {
+            log::debug!("The TLS handshake with {} timed out", peer);
+            return;
+        }
+    };
+    let mut service = warp::service(filter);
+    let service = service_fn(move |mut request: Request<Body>| {
+        request.extensions_mut().insert(Peer(peer));
+        service.call(request)
+    });
+    if let Err(err) = Http::new().serve_connection(stream, service).await {
+        log::debug!("Cannot serve {}: {}", peer, err);
+    }
+}
+
+/// Serves `filter` over TLS to the clients of `listener` until `stop` completes.
+///
+/// The files of the certificate are checked every `check_interval` and the renewed certificate
+/// is used for the next connections once they stop changing, calling `renewed` with it.
+/// A renewed certificate that cannot be used is logged and the previous one kept.
+async fn serve_tls<F, R>(
+    filter: F,
+    listener: TcpListener,
+    config: TlsConfig,
+    mut current: Acceptor,
+    check_interval: Duration,
+    stop: AsyncStopFlag,
+    renewed: R,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+    R: Fn(&Certificate),
+{
+    tokio::pin!(stop);
+    let mut interval = time::interval_at(Instant::now() + check_interval, check_interval);
+    // Renewed certificate seen in the last check and the last one that couldn't be used
+    let mut changed = None;
+    let mut rejected = None;
+    loop {
+        tokio::select! {
+            _ = &mut stop => return,
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((stream, peer)) => {
+                        let connection =
+                            serve_connection(filter.clone(), current.tls.clone(), stream, peer);
+                        tokio::spawn(connection);
+                    }
+                    // Most likely out of file descriptors, which takes a while to be fixed
+                    Err(err) => {
+                        log::error!("Cannot accept a connection: {}", err);
+                        time::sleep(ACCEPT_RETRY_DELAY).await;
+                    }
+                }
+                continue;
+            }
+            _ = interval.tick() => {}
+        }
+        let certificate = match Certificate::read(&config) {
+            Ok(certificate) => certificate,
+            Err(err) => {
+                log::warn!("Cannot read the TLS certificate: {}", err);
+                continue;
+            }
+        };
+        if certificate == current.certificate || rejected.as_ref() == Some(&certificate) {
+            changed = None;
+            continue;
+        }
+        // The files may still be being written
+        if changed.as_ref() != Some(&certificate) {
+            changed = Some(certificate);
+            continue;
+        }
+        changed = None;
+
+        match Acceptor::new(certificate.clone()).await {
+            Ok(acceptor) => {
+                log::info!("Serving the renewed TLS certificate");
+                renewed(&acceptor.certificate);
+                current = acceptor;
+            }
+            Err(err) => {
+                log::error!(
+                    "Cannot use the renewed TLS certificate, keeping the previous one: {}",
+                    err
+                );
+                rejected = Some(certificate);
+            }
+        }
+    }
+}
+
+/// Listens for the updates Telegram posts.
+///
+/// Fails when the webhook cannot be setup or its address cannot be listened on.
+pub async fn webhook(
+    bot: AutoSend<Bot>,
+    config: &WebhookConfig,
+) -> Result<impl update_listeners::UpdateListener<Infallible>> {
+    let security = WebhookSecurity::new(config);
+    let (tx, rx) = mpsc::unbounded_channel();
+    let server = updates(config.path.clone(), Arc::new(security.clone()), tx);
     let (stop_token, stop_flag) = AsyncStopToken::new_pair();
 
-    let addr = format!("0.0.0.0:{}", port).parse::<SocketAddr>().unwrap();
-    let server = warp::serve(server);
-    let (_addr, fut) = server.bind_with_graceful_shutdown(addr, stop_flag);
+    // Listens before pointing Telegram to the webhook, so a failure leaves the previous one set
+    let (serve, certificate) = match &config.tls {
+        Some(tls) => {
+            let certificate = Certificate::read(tls).context("Cannot read the TLS certificate")?;
+            let acceptor = Acceptor::new(certificate).await?;
+            let listener = TcpListener::bind(config.bind)
+                .await
+                .with_context(|| format!("Cannot listen on {}", config.bind))?;
+            let uploaded = Some(acceptor.certificate.cert.clone()).filter(|_| tls.self_signed);
+            // Telegram only trusts the renewed self-signed certificate once it is uploaded
+            let renewed = {
+                let bot = bot.inner().clone();
+                let url = config.url.clone();
+                let secret = security.secret.clone();
+                let self_signed = tls.self_signed;
+                move |certificate: &Certificate| {
+                    if !self_signed {
+                        return;
+                    }
+                    let (bot, url, secret) = (bot.clone(), url.clone(), secret.clone());
+                    let cert = certificate.cert.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = set_webhook(&bot, &url, &secret, Some(&cert)).await {
+                            log::error!("Cannot upload the renewed certificate: {}", err);
+                        }
+                    });
+                }
+            };
+            let serve = serve_tls(
+                server,
+                listener,
+                tls.clone(),
+                acceptor,
+                CERT_CHECK_INTERVAL,
+                stop_flag,
+                renewed,
+            );
+            (serve.boxed(), uploaded)
+        }
+        None => {
+            let (_addr, serve) = warp::serve(server)
+                .try_bind_with_graceful_shutdown(config.bind, stop_flag)
+                .with_context(|| format!("Cannot listen on {}", config.bind))?;
+            (serve.boxed(), None)
+        }
+    };
 
-    // You might want to use serve.key_path/serve.cert_path methods here to
-    // setup a self-signed TLS certificate.
+    set_webhook(
+        bot.inner(),
+        &config.url,
+        &security.secret,
+        certificate.as_deref(),
+    )
+    .await?;
+    tokio::spawn(serve);
 
-    tokio::spawn(fut);
+    status::receiving_updates();
     let stream = UnboundedReceiverStream::new(rx);
 
     fn streamf<S, T>(state: &mut (S, T)) -> &mut S {
         &mut state.0
     }
 
-    StatefulListener::new(
+    Ok(StatefulListener::new(
         (stream, stop_token),
         streamf,
         |state: &mut (_, AsyncStopToken)| state.1.clone(),
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::dispatching::stop_token::StopToken;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::mpsc::UnboundedReceiver,
+    };
+    use tokio_rustls::client::TlsStream;
+
+    const UPDATE: &str = r#"{"update_id": 1, "message": {"message_id": 1, "date": 0, "chat": {"id": 1, "type": "private", "first_name": "Alice"}, "from": {"id": 1, "is_bot": false, "first_name": "Alice"}, "text": "/help"}}"#;
+
+    fn security(allowed_ips: &str, behind_proxy: bool) -> WebhookSecurity {
+        WebhookSecurity {
+            secret: "secret".into(),
+            allowed_ips: allowed_ips
+                .split(',')
+                .filter(|r| !r.is_empty())
+                .map(|r| r.parse().unwrap())
+                .collect(),
+            behind_proxy,
+        }
+    }
+
+    fn request(body: &str) -> warp::test::RequestBuilder {
+        warp::test::request()
+            .method("POST")
+            .path("/bot123")
+            .header(SECRET_HEADER, "secret")
+            .remote_addr("149.154.167.1:443".parse().unwrap())
+            .body(body)
+    }
+
+    async fn post(
+        security: WebhookSecurity,
+        request: warp::test::RequestBuilder,
+    ) -> (StatusCode, UnboundedReceiver<Result<Update, Infallible>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let filter = updates("bot123".into(), Arc::new(security), tx);
+        (request.reply(&filter).await.status(), rx)
+    }
+
+    #[tokio::test]
+    async fn updates_with_the_secret_are_accepted() {
+        let (status, mut rx) = post(security("", false), request(UPDATE)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn updates_without_the_secret_are_refused() {
+        let (status, mut rx) = post(
+            security("", false),
+            request(UPDATE).header(SECRET_HEADER, "guess"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(rx.try_recv().is_err());
+
+        let request = warp::test::request()
+            .method("POST")
+            .path("/bot123")
+            .body(UPDATE);
+        let (status, _) = post(security("", false), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unparseable_updates_are_acknowledged() {
+        for body in ["not json", r#"{"update_id": "one"}"#].iter() {
+            let (status, mut rx) = post(security("", false), request(body)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn big_bodies_are_refused() {
+        let body = " ".repeat(MAX_BODY_SIZE as usize + 1);
+        let (status, _) = post(security("", false), request(&body)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn other_paths_are_not_found() {
+        let (status, _) = post(security("", false), request(UPDATE).path("/bot456")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn only_allowed_addresses_can_send_updates() {
+        let allowed = "149.154.160.0/20,91.108.4.0/22";
+        let (status, _) = post(security(allowed, false), request(UPDATE)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let stranger = request(UPDATE).remote_addr("10.0.0.1:443".parse().unwrap());
+        let (status, _) = post(security(allowed, false), stranger).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Behind a proxy the last forwarded address is the one that counts
+        let proxied = request(UPDATE)
+            .remote_addr("10.0.0.1:443".parse().unwrap())
+            .header("x-forwarded-for", "10.0.0.2, 91.108.4.10");
+        let (status, _) = post(security(allowed, true), proxied).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let spoofed = request(UPDATE).header("x-forwarded-for", "91.108.4.10, 10.0.0.2");
+        let (status, _) = post(security(allowed, true), spoofed).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn the_address_of_tls_clients_is_checked() {
+        let allowed = "149.154.160.0/20";
+        let request = |peer: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/bot123")
+                .header(SECRET_HEADER, "secret")
+                .extension(Peer(peer.parse().unwrap()))
+                .body(UPDATE)
+        };
+        let (status, _) = post(security(allowed, false), request("149.154.167.1:443")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(security(allowed, false), request("10.0.0.1:443")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    /// Opens a TLS connection to `addr`, trusting any certificate.
+    async fn connect(addr: SocketAddr) -> TlsStream<TcpStream> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        connector().connect(name, stream).await.unwrap()
+    }
+
+    /// DER of the certificate served at `addr`.
+    async fn served_certificate(addr: SocketAddr) -> Vec<u8> {
+        let stream = connect(addr).await;
+        let certificates = stream.get_ref().1.peer_certificates().unwrap();
+        certificates[0].0.clone()
+    }
+
+    /// Body of the answer to a GET over `stream`.
+    async fn get(stream: &mut TlsStream<TcpStream>) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.rsplit("\r\n").next().unwrap().to_string()
+    }
+
+    fn der(pem: &[u8]) -> Vec<u8> {
+        rustls_pemfile::certs(&mut &pem[..]).unwrap().remove(0)
+    }
+
+    fn certificate(cert: &[u8], key: &[u8]) -> Certificate {
+        Certificate {
+            cert: cert.to_vec(),
+            key: key.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn renewed_certificates_are_served() {
+        let first = include_bytes!("../tests/tls/first.pem");
+        let second = include_bytes!("../tests/tls/second.pem");
+        let dir = std::env::temp_dir().join(format!("gofish-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            self_signed: true,
+        };
+        fs::write(&config.cert, first).unwrap();
+        fs::write(&config.key, include_bytes!("../tests/tls/first.key")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = Acceptor::new(Certificate::read(&config).unwrap())
+            .await
+            .unwrap();
+
+        let (stop_token, stop_flag) = AsyncStopToken::new_pair();
+        let (renewed_tx, mut renewed) = mpsc::unbounded_channel();
+        let filter = warp::any().map(|| "fish");
+        let interval = Duration::from_millis(50);
+        let server = tokio::spawn(serve_tls(
+            filter,
+            listener,
+            config.clone(),
+            acceptor,
+            interval,
+            stop_flag,
+            move |certificate: &Certificate| renewed_tx.send(certificate.cert.clone()).unwrap(),
+        ));
+        assert_eq!(served_certificate(addr).await, der(first));
+        let mut before_renewal = connect(addr).await;
+
+        // A certificate that doesn't match the key is not served
+        fs::write(&config.cert, second).unwrap();
+        time::sleep(interval * 10).await;
+        assert_eq!(served_certificate(addr).await, der(first));
+        assert!(renewed.try_recv().is_err());
+
+        fs::write(&config.key, include_bytes!("../tests/tls/second.key")).unwrap();
+        time::sleep(interval * 10).await;
+        assert_eq!(served_certificate(addr).await, der(second));
+        assert_eq!(renewed.try_recv().unwrap(), second.to_vec());
+
+        // Connections made before the renewal are still answered
+        assert_eq!(get(&mut before_renewal).await, "fish");
+
+        stop_token.stop();
+        time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unusable_certificates_are_refused() {
+        let first = include_bytes!("../tests/tls/first.pem");
+        let first_key = include_bytes!("../tests/tls/first.key");
+        let second_key = include_bytes!("../tests/tls/second.key");
+        assert!(Acceptor::new(certificate(first, first_key)).await.is_ok());
+        assert!(Acceptor::new(certificate(first, second_key)).await.is_err());
+        assert!(Acceptor::new(certificate(first, b"not a key"))
+            .await
+            .is_err());
+        assert!(Acceptor::new(certificate(b"not a certificate", first_key))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn ip_ranges_are_parsed() {
+        let range: IpRange = "149.154.160.0/20".parse().unwrap();
+        assert!(range.contains("149.154.175.255".parse().unwrap()));
+        assert!(!range.contains("149.154.176.0".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let single: IpRange = "10.0.0.1".parse().unwrap();
+        assert!(single.contains("10.0.0.1".parse().unwrap()));
+        assert!(!single.contains("10.0.0.2".parse().unwrap()));
+
+        let everything: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("8.8.8.8".parse().unwrap()));
+        let v6: IpRange = "2001:b28:f23d::/48".parse().unwrap();
+        assert!(v6.contains("2001:b28:f23d:f001::a".parse().unwrap()));
+
+        for invalid in ["", "10.0.0.1/33", "::/129", "telegram", "10.0.0/8"].iter() {
+            assert!(invalid.parse::<IpRange>().is_err(), "{}", invalid);
+        }
+    }
 }
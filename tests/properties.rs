@@ -0,0 +1,192 @@
+use gofish::command::Command;
+use gofish::entities::{
+    card::Rank,
+    config::{GameConfig, Variant, MAX_RANKS},
+    game::{Action, Game, GameState, Target},
+    player::Difficulty,
+};
+use gofish::keyboards::CallbackData;
+use gofish::session::{
+    game::GameSession,
+    messages::{GameCommand, Identity},
+};
+use gofish::storage::sqlite::SqliteStore;
+use gofish::templates::invalid_card;
+use proptest::prelude::*;
+use std::sync::Arc;
+use teloxide::utils::command::BotCommand;
+
+const PLAYERS: [&str; 4] = ["1", "2", "3", "4"];
+
+fn rank() -> impl Strategy<Value = Rank> {
+    (1..=MAX_RANKS).prop_map(|value| Rank::new(value).unwrap())
+}
+
+/// Any id, mostly the ones of the players.
+fn player_id() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => proptest::sample::select(&PLAYERS[..]).prop_map(String::from),
+        1 => ".*",
+    ]
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        1 => Just(Action::Start),
+        2 => (player_id(), proptest::option::of(Just(Difficulty::Easy)))
+            .prop_map(|(id, bot)| Action::Join(id.clone(), id, None, bot)),
+        8 => (player_id(), 0..6usize, rank()).prop_map(|(id, to, rank)| Action::Ask(id, to, rank)),
+        4 => (player_id(), rank()).prop_map(|(id, rank)| Action::Draw(id, rank)),
+        1 => player_id().prop_map(Action::Leave),
+    ]
+}
+
+fn config() -> impl Strategy<Value = GameConfig> {
+    (
+        prop_oneof![Just(Variant::Standard), Just(Variant::SuddenDeath)],
+        proptest::option::of(1..=10u8),
+        1..=MAX_RANKS,
+        prop_oneof![Just(2u8), Just(4u8)],
+        any::<bool>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(variant, hand_size, ranks, group_size, fish_again, seed)| GameConfig {
+                variant,
+                hand_size,
+                ranks,
+                group_size,
+                fish_again,
+                seed: Some(seed),
+                ..GameConfig::default()
+            },
+        )
+}
+
+fn game_command() -> impl Strategy<Value = GameCommand> {
+    prop_oneof![
+        Just(GameCommand::Start),
+        Just(GameCommand::Join),
+        Just(GameCommand::AddBot(Difficulty::Hard)),
+        Just(GameCommand::Status),
+        (".*", ".*").prop_map(|(to, card)| GameCommand::Ask(Target::Query(to), card)),
+        Just(GameCommand::Leave),
+        ".*".prop_map(|player| GameCommand::Kick(Target::Query(player))),
+        Just(GameCommand::Seed),
+        (0..100usize).prop_map(GameCommand::History),
+        Just(GameCommand::Export),
+        (0..8usize).prop_map(GameCommand::ChooseOpponent),
+        (0..8usize, rank()).prop_map(|(to, rank)| GameCommand::ChooseCard(to, rank)),
+    ]
+}
+
+fn identity(id: &str) -> Identity {
+    Identity {
+        id: id.into(),
+        name: format!("Player {}", id),
+        username: None,
+    }
+}
+
+proptest! {
+    #[test]
+    fn parsing_commands_never_panics(text in "/(ask|kick|newgame|addbot|history|leaderboard|join)?.*") {
+        let _ = Command::parse(&text, "gofish");
+    }
+
+    #[test]
+    fn asking_keeps_the_last_word_as_the_card(to in "[a-z@ ]{0,20}", card in "\\S{1,10}") {
+        match Command::parse(&format!("/ask {} {}", to, card), "gofish") {
+            Ok(Command::Ask { to: parsed, card: parsed_card }) => {
+                prop_assert_eq!(parsed, to.split_whitespace().collect::<Vec<_>>().join(" "));
+                prop_assert_eq!(parsed_card, card);
+            }
+            other => prop_assert!(false, "{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parsed_ranks_are_valid(text in ".*") {
+        if let Ok(rank) = text.parse::<Rank>() {
+            prop_assert!((1..=MAX_RANKS).contains(&rank.value()));
+        }
+    }
+
+    #[test]
+    fn numbers_out_of_range_are_not_ranks(number in 14u64..) {
+        prop_assert!(number.to_string().parse::<Rank>().is_err());
+    }
+
+    #[test]
+    fn parsed_configs_are_valid(text in "((rules|hand|ranks|group|showdrawn|fishagain|timeout|seed)=[0-9a-z]{0,4} ?){0,4}") {
+        if let Ok(config) = text.parse::<GameConfig>() {
+            prop_assert!((1..=MAX_RANKS).contains(&config.ranks));
+            prop_assert!(config.group_size == 2 || config.group_size == 4);
+            prop_assert!(config.hand_size.map_or(true, |size| (1..=10).contains(&size)));
+        }
+    }
+
+    #[test]
+    fn callback_data_round_trips(chat_id in any::<i64>(), to in any::<usize>(), rank in rank()) {
+        for data in [CallbackData::ChooseOpponent(chat_id, to), CallbackData::ChooseCard(chat_id, to, rank)].iter() {
+            prop_assert_eq!(data.to_string().parse::<CallbackData>(), Ok(*data));
+        }
+    }
+
+    #[test]
+    fn parsing_callback_data_never_panics(text in "(to|card)?(:-?[0-9]{0,20}){0,4}|.*") {
+        let _ = text.parse::<CallbackData>();
+    }
+
+    #[test]
+    fn games_never_panic(config in config(), actions in proptest::collection::vec(action(), 0..300)) {
+        let mut game = Game::new(config);
+        for action in actions {
+            let moves = game.history.len();
+            let state = game.state.clone();
+            match game.execute(action) {
+                Ok(_) => prop_assert_eq!(game.history.len(), moves + 1),
+                // Failed actions change nothing
+                Err(_) => {
+                    prop_assert_eq!(game.history.len(), moves);
+                    prop_assert_eq!(&game.state, &state);
+                }
+            }
+            if let GameState::Asking(i) | GameState::Drawing(i) = game.state {
+                prop_assert!(i < game.players.len());
+            }
+        }
+    }
+
+    #[test]
+    fn sessions_never_panic(
+        config in config(),
+        commands in proptest::collection::vec((proptest::sample::select(&PLAYERS[..]), game_command()), 0..100),
+    ) {
+        let db = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let mut session = GameSession::new(1, config, db.clone(), db);
+        for (id, command) in commands {
+            session.handle(&identity(id), command);
+            if session.is_over() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn asking_for_numbers_out_of_range_is_an_invalid_card(number in 14u32..) {
+        let db = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let mut session = GameSession::new(1, GameConfig::default(), db.clone(), db);
+        for id in PLAYERS[..2].iter() {
+            session.handle(&identity(id), GameCommand::Join);
+        }
+        session.handle(&identity("1"), GameCommand::Start);
+        let asker = match session.game().state {
+            GameState::Asking(i) => session.game().players[i].id.clone(),
+            _ => unreachable!(),
+        };
+        let card = number.to_string();
+        let response = session.handle(&identity(&asker), GameCommand::Ask(Target::Query("0".into()), card.clone()));
+        prop_assert_eq!(response.error, Some(invalid_card(&card)));
+    }
+}
@@ -0,0 +1,192 @@
+use gofish::entities::{
+    config::{GameConfig, Variant},
+    game::{Action, Game, GameState, TurnEvent},
+    player::Difficulty,
+};
+use gofish::session::{
+    game::GameSession,
+    messages::{GameCommand, Identity},
+};
+use gofish::storage::sqlite::SqliteStore;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::sync::Arc;
+
+// Games played with random rules and moves
+const GAMES: u64 = 2000;
+// Actions after which a game is considered stuck
+const MAX_STEPS: usize = 20_000;
+
+/// Random rules, the same for the same generator.
+fn random_config(rng: &mut ChaCha8Rng) -> GameConfig {
+    GameConfig {
+        variant: *[Variant::Standard, Variant::SuddenDeath]
+            .choose(rng)
+            .unwrap(),
+        hand_size: if rng.gen() {
+            Some(rng.gen_range(1..=10))
+        } else {
+            None
+        },
+        ranks: rng.gen_range(1..=13),
+        group_size: *[2, 4].choose(rng).unwrap(),
+        fish_again: rng.gen(),
+        seed: Some(rng.gen()),
+        ..GameConfig::default()
+    }
+}
+
+/// Any of the moves the player on turn could make, sometimes leaving instead.
+fn random_action(game: &Game, rng: &mut ChaCha8Rng) -> Action {
+    let (index, asking) = match game.state {
+        GameState::Asking(index) => (index, true),
+        GameState::Drawing(index) => (index, false),
+        _ => unreachable!(),
+    };
+    let player = &game.players[index];
+    if rng.gen_ratio(1, 200) {
+        let leaving = game.players.choose(rng).unwrap();
+        return Action::Leave(leaving.id.clone());
+    }
+    let rank = *player
+        .ranks()
+        .choose(rng)
+        .expect("A player on turn without cards");
+    if !asking {
+        return Action::Draw(player.id.clone(), rank);
+    }
+    let opponents: Vec<usize> = (0..game.players.len()).filter(|&i| i != index).collect();
+    Action::Ask(player.id.clone(), *opponents.choose(rng).unwrap(), rank)
+}
+
+/// Books made so far, counting those of the players who left.
+struct Books {
+    left: usize,
+    made: usize,
+}
+
+fn check_invariants(game: &Game, books: &Books) {
+    let config = &game.config;
+    let group_size = config.group_size as usize;
+    let scores: usize = game.players.iter().map(|p| p.score as usize).sum();
+    assert_eq!(
+        scores + books.left,
+        books.made,
+        "scores don't add up to the books"
+    );
+
+    let in_hands: usize = game.players.iter().map(|p| p.cards.len()).sum();
+    let cards = game.deck.len() + in_hands + books.made * group_size;
+    assert_eq!(
+        cards,
+        config.ranks as usize * 4,
+        "cards were lost or made up"
+    );
+
+    match &game.state {
+        GameState::Asking(i) | GameState::Drawing(i) => {
+            assert!(*i < game.players.len());
+            assert!(game.players.len() > 1);
+            assert!(game.players[*i].has_cards());
+        }
+        GameState::GameOver(results) => {
+            let best = game.players.iter().map(|p| p.score).max().unwrap_or(0);
+            assert_eq!(results.score, best);
+            let winners: Vec<&String> = game
+                .players
+                .iter()
+                // Nobody wins without groups, unless everyone else left
+                .filter(|p| p.score == best && (best > 0 || game.players.len() == 1))
+                .map(|p| &p.name)
+                .collect();
+            assert_eq!(results.winners.iter().collect::<Vec<_>>(), winners);
+        }
+        GameState::Waiting => unreachable!(),
+    }
+}
+
+fn play(number: u64) {
+    let mut rng = ChaCha8Rng::seed_from_u64(number);
+    let config = random_config(&mut rng);
+    let mut game = Game::new(config.clone());
+    for i in 0..rng.gen_range(2..=6) {
+        let join = Action::Join(i.to_string(), format!("Player {}", i), None, None);
+        game.execute(join).unwrap();
+    }
+    game.execute(Action::Start).unwrap();
+    let mut books = Books {
+        left: 0,
+        made: game.players.iter().map(|p| p.score as usize).sum(),
+    };
+    check_invariants(&game, &books);
+
+    let mut steps = 0;
+    while !matches!(game.state, GameState::GameOver(_)) {
+        steps += 1;
+        assert!(
+            steps < MAX_STEPS,
+            "game {} never ended: {:?}",
+            number,
+            config
+        );
+        let action = random_action(&game, &mut rng);
+        if let Action::Leave(id) = &action {
+            books.left += game.get_player_by_id(id).unwrap().score as usize;
+        }
+        let events = game
+            .execute(action.clone())
+            .unwrap_or_else(|err| panic!("game {}: {:?} failed: {}", number, action, err));
+        books.made += events
+            .iter()
+            .filter(|e| matches!(e, TurnEvent::Group(_)))
+            .count();
+        check_invariants(&game, &books);
+    }
+
+    // Without anybody leaving, standard games only end once every card is grouped
+    let left = game
+        .history
+        .iter()
+        .any(|m| matches!(m.action, Action::Leave(_)));
+    if config.variant == Variant::Standard && !left {
+        assert!(game.deck.is_empty());
+        assert!(game.players.iter().all(|p| !p.has_cards()));
+    }
+    // Every game can be replayed from its export
+    let export = game.export();
+    let replayed = export.replay(export.moves.len()).unwrap();
+    assert_eq!(replayed.state, game.state);
+}
+
+#[test]
+fn random_games_keep_every_invariant() {
+    for number in 0..GAMES {
+        play(number);
+    }
+}
+
+#[test]
+fn bots_play_games_to_the_end() {
+    let db = Arc::new(SqliteStore::open(":memory:").unwrap());
+    let host = Identity {
+        id: "host".into(),
+        name: "Host".into(),
+        username: None,
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    for _ in 0..200 {
+        let config = random_config(&mut rng);
+        let mut session = GameSession::new(1, config.clone(), db.clone(), db.clone());
+        for _ in 0..rng.gen_range(2..=6) {
+            let difficulty = *[Difficulty::Easy, Difficulty::Hard]
+                .choose(&mut rng)
+                .unwrap();
+            let response = session.handle(&host, GameCommand::AddBot(difficulty));
+            assert_eq!(response.error, None);
+        }
+        let response = session.handle(&host, GameCommand::Start);
+        assert_eq!(response.error, None);
+        assert!(session.is_over(), "bots didn't finish: {:?}", config);
+    }
+}